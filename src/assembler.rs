@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+
+use ethers_core::abi::Uint;
+
+use crate::{
+    codegen::Bytecode,
+    utils::{
+        errors::AssemblyError,
+        evm::Opcode,
+        span::{Span, Spanned},
+    },
+};
+
+/// Size of the immediate of a generic PUSH of a label
+const LABEL_SIZE: usize = 2;
+
+/// The operand of a PUSH or INVALID instruction
+enum Immediate<'a> {
+    Value(Uint),
+    Label(&'a str),
+}
+
+/// Assemble mnemonics separated by whitespace into bytecode
+///
+/// ```text
+/// PUSH 2 PUSH 3 PUSH 4 MUL DIV ; comments run until the end of the line
+/// end: PUSH2 end               // labels push the offset they are defined at
+/// ```
+///
+/// - `PUSH1` to `PUSH32` take a decimal or `0x` hexadecimal immediate, or a label
+/// - `PUSH` picks the smallest PUSH for its value, and PUSH2 for a label
+/// - `INVALID 0xee` emits a raw byte
+/// - Offsets like `0x0000:` are skipped, so the disassembler output assembles back
+/// - A PUSH ending the code with fewer hexadecimal bytes than it takes stays truncated,
+///   e.g. `PUSH3 0x0102`, like the disassembler prints code ending inside an immediate
+pub fn assemble(source: &str) -> Result<Bytecode, AssemblyError> {
+    let mut code = Vec::new();
+    let mut labels = HashMap::new();
+    // Label references to patch once every label is known
+    let mut fixups = Vec::new();
+    let mut words = words(source).into_iter().peekable();
+    while let Some(word) = words.next() {
+        if let Some(name) = word.node.strip_suffix(':') {
+            if is_label(name) {
+                if labels.insert(name, code.len()).is_some() {
+                    return Err(AssemblyError::DuplicateLabel(name.to_owned(), word.span));
+                }
+                continue;
+            }
+            if is_offset(name) {
+                continue;
+            }
+        }
+
+        let mnemonic = word.node;
+        let (opcode, operand) = if mnemonic.eq_ignore_ascii_case("INVALID") {
+            match immediate(words.next(), &word)? {
+                (Immediate::Value(value), span) => code.extend(bytes(value, 1, span)?),
+                (Immediate::Label(name), span) => {
+                    return Err(AssemblyError::InvalidImmediate(name.to_owned(), span))
+                }
+            }
+            continue;
+        } else if mnemonic.eq_ignore_ascii_case("PUSH") {
+            match immediate(words.next(), &word)? {
+                (Immediate::Value(value), span) => {
+                    let size = value.bits().div_ceil(8);
+                    // Safe unwrap here, a Uint is at most 32 bytes long
+                    code.push(Opcode::push(size).unwrap() as u8);
+                    code.extend(bytes(value, size, span)?);
+                    continue;
+                }
+                // Safe unwrap here, the size is at most 32
+                (label, span) => (Opcode::push(LABEL_SIZE).unwrap(), Some((label, span))),
+            }
+        } else {
+            let opcode = Opcode::from_mnemonic(mnemonic)
+                .ok_or_else(|| AssemblyError::UnknownMnemonic(mnemonic.to_owned(), word.span))?;
+            let size = opcode.immediate_size();
+            if let Some(immediate) = words.peek().and_then(|w| truncated(w.node, size)) {
+                if words.len() == 1 {
+                    code.push(opcode as u8);
+                    code.extend(immediate);
+                    break;
+                }
+            }
+            match size {
+                0 => (opcode, None),
+                _ => (opcode, Some(immediate(words.next(), &word)?)),
+            }
+        };
+
+        code.push(opcode as u8);
+        let size = opcode.immediate_size();
+        match operand {
+            None => (),
+            Some((Immediate::Value(value), span)) => code.extend(bytes(value, size, span)?),
+            Some((Immediate::Label(name), span)) => {
+                fixups.push((code.len(), size, name, span));
+                code.extend(vec![0; size]);
+            }
+        }
+    }
+
+    for (position, size, name, span) in fixups {
+        let offset = labels
+            .get(name)
+            .ok_or_else(|| AssemblyError::UndefinedLabel(name.to_owned(), span))?;
+        code[position..position + size].copy_from_slice(&bytes(Uint::from(*offset), size, span)?);
+    }
+    Ok(Bytecode::from(code))
+}
+
+/// Split the source into words, dropping `;` and `//` comments
+fn words(source: &str) -> Vec<Spanned<&str>> {
+    let mut words = Vec::new();
+    let mut line_start = 0;
+    for line in source.split_inclusive('\n') {
+        let end = [line.find(';'), line.find("//")]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(line.len());
+        let code = &line[..end];
+        let mut start = None;
+        for (i, c) in code.char_indices().chain(std::iter::once((end, ' '))) {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    words.push(Spanned::new(
+                        &code[s..i],
+                        Span::new(line_start + s, line_start + i),
+                    ));
+                    start = None;
+                }
+                (false, None) => start = Some(i),
+                _ => (),
+            }
+        }
+        line_start += line.len();
+    }
+    words
+}
+
+/// Parse the immediate following `instruction`
+fn immediate<'a>(
+    word: Option<Spanned<&'a str>>,
+    instruction: &Spanned<&str>,
+) -> Result<(Immediate<'a>, Span), AssemblyError> {
+    let missing = || AssemblyError::MissingImmediate(instruction.node.to_owned(), instruction.span);
+    let word = word.ok_or_else(missing)?;
+    let text = word.node;
+    let invalid = || AssemblyError::InvalidImmediate(text.to_owned(), word.span);
+
+    let immediate = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        if hex.is_empty() || hex.len() > 64 {
+            return Err(invalid());
+        }
+        Immediate::Value(Uint::from_str_radix(hex, 16).map_err(|_| invalid())?)
+    } else if text.chars().all(|c| c.is_ascii_digit()) {
+        Immediate::Value(Uint::from_dec_str(text).map_err(|_| invalid())?)
+    } else if Opcode::from_mnemonic(text).is_some() || text.eq_ignore_ascii_case("PUSH") {
+        // Most likely a forgotten immediate
+        return Err(missing());
+    } else if is_label(text) {
+        Immediate::Label(text)
+    } else {
+        return Err(invalid());
+    };
+    Ok((immediate, word.span))
+}
+
+/// The last `size` bytes of `value`, as long as the rest of them are zeros
+fn bytes(value: Uint, size: usize, span: Span) -> Result<Vec<u8>, AssemblyError> {
+    if value.bits().div_ceil(8) > size {
+        return Err(AssemblyError::ImmediateTooLarge {
+            value: value.to_string(),
+            size,
+            span,
+        });
+    }
+    let mut word = [0u8; 32];
+    value.to_big_endian(&mut word);
+    Ok(word[32 - size..].to_vec())
+}
+
+/// The bytes a hexadecimal immediate spells out, if there are fewer of them than `size`
+fn truncated(text: &str, size: usize) -> Option<Vec<u8>> {
+    let hex = text.strip_prefix("0x").or(text.strip_prefix("0X"))?;
+    let bytecode = hex.parse::<Bytecode>().ok()?;
+    (bytecode.as_ref().len() < size).then(|| bytecode.as_ref().to_vec())
+}
+
+/// Whether a word is a label name
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether a word is an offset like the disassembler prints them
+fn is_offset(word: &str) -> bool {
+    word.strip_prefix("0x")
+        .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::tests::bytecode,
+        utils::evm::{disassemble, Outcome, EVM},
+    };
+
+    #[test]
+    fn test_assemble() {
+        let b = assemble("PUSH 2 PUSH 3 PUSH 4 MUL DIV").unwrap();
+        assert_eq!(b, bytecode("6002600360040204".to_owned()));
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::from(6))))
+        );
+
+        // Explicit sizes, hexadecimal and comments
+        let source = "
+            push2 0x1 ; pads to 2 bytes
+            PUSH32 255 // pads to 32 bytes
+            PUSH 0
+            ADD ADD
+        ";
+        let expected = format!("610001 7F{:064X} 5F 01 01", 255).replace(' ', "");
+        assert_eq!(assemble(source), Ok(bytecode(expected)));
+        assert_eq!(assemble(""), Ok(bytecode(String::new())));
+
+        // Labels push their offset, even before they are defined
+        let source = "PUSH end PUSH1 start start: INVALID 0xee end: PUSH4 end";
+        assert_eq!(
+            assemble(source),
+            Ok(bytecode("6100066005EE6300000006".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let listing = |b: &Bytecode| {
+            disassemble(b)
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let b = bytecode("6002610100025FEE7F".to_owned() + &"AB".repeat(32) + "0A");
+        assert_eq!(assemble(&listing(&b)), Ok(b));
+
+        // Ending inside the immediate of a PUSH
+        let b = bytecode("6002620102".to_owned());
+        assert!(listing(&b).ends_with("PUSH3 0x0102 ; truncated, 2 of 3 bytes"));
+        assert_eq!(assemble(&listing(&b)), Ok(b));
+        let b = bytecode("600261".to_owned());
+        assert_eq!(assemble(&listing(&b)), Ok(b));
+        // Anywhere else the immediate is padded
+        assert_eq!(
+            assemble("PUSH3 0x0102 ADD"),
+            Ok(bytecode("6200010201".to_owned()))
+        );
+        assert_eq!(assemble("PUSH3 0x102"), Ok(bytecode("62000102".to_owned())));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(
+            assemble("PUSH1 1 FOO"),
+            Err(AssemblyError::UnknownMnemonic(
+                "FOO".to_owned(),
+                Span::new(8, 11)
+            ))
+        );
+        assert_eq!(
+            assemble("PUSH1"),
+            Err(AssemblyError::MissingImmediate(
+                "PUSH1".to_owned(),
+                Span::new(0, 5)
+            ))
+        );
+        assert_eq!(
+            assemble("PUSH ADD"),
+            Err(AssemblyError::MissingImmediate(
+                "PUSH".to_owned(),
+                Span::new(0, 4)
+            ))
+        );
+        assert_eq!(
+            assemble("PUSH1 0xZZ"),
+            Err(AssemblyError::InvalidImmediate(
+                "0xZZ".to_owned(),
+                Span::new(6, 10)
+            ))
+        );
+        assert_eq!(
+            assemble("\nPUSH1 256"),
+            Err(AssemblyError::ImmediateTooLarge {
+                value: "256".to_owned(),
+                size: 1,
+                span: Span::new(7, 10)
+            })
+        );
+        assert_eq!(
+            assemble("a: a: ADD"),
+            Err(AssemblyError::DuplicateLabel(
+                "a".to_owned(),
+                Span::new(3, 5)
+            ))
+        );
+        assert_eq!(
+            assemble("PUSH nowhere"),
+            Err(AssemblyError::UndefinedLabel(
+                "nowhere".to_owned(),
+                Span::new(5, 12)
+            ))
+        );
+        assert_eq!(
+            assemble("INVALID here here:"),
+            Err(AssemblyError::InvalidImmediate(
+                "here".to_owned(),
+                Span::new(8, 12)
+            ))
+        );
+    }
+}
@@ -0,0 +1,577 @@
+use std::fmt::Display;
+
+use ethers_core::abi::Uint;
+
+use crate::utils::{
+    span::Span,
+    tokens::{Associativity, Token},
+};
+
+/// A prefix operator
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum UnaryOp {
+    /// Negation
+    Neg,
+    /// Bitwise not
+    BitNot,
+    /// Logical not, 1 for 0 and 0 otherwise
+    Not,
+}
+
+impl UnaryOp {
+    /// The prefix operator a token stands for
+    pub fn from_token(token: &Token) -> Option<UnaryOp> {
+        match token {
+            Token::Sub | Token::Neg => Some(UnaryOp::Neg),
+            Token::BitNot => Some(UnaryOp::BitNot),
+            Token::Not => Some(UnaryOp::Not),
+            _ => None,
+        }
+    }
+
+    /// The token of this operator
+    pub fn token(&self) -> Token {
+        match self {
+            UnaryOp::Neg => Token::Neg,
+            UnaryOp::BitNot => Token::BitNot,
+            UnaryOp::Not => Token::Not,
+        }
+    }
+
+    /// Name of this operator in S-expressions
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "neg",
+            UnaryOp::BitNot => "not",
+            UnaryOp::Not => "lnot",
+        }
+    }
+}
+
+/// An infix operator
+///
+/// Comparisons and logical operations are 1 when true and 0 when false,
+/// any value other than 0 being true for their operands.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// The infix operator a token stands for
+    pub fn from_token(token: &Token) -> Option<BinaryOp> {
+        match token {
+            Token::Add => Some(BinaryOp::Add),
+            Token::Sub => Some(BinaryOp::Sub),
+            Token::Mul => Some(BinaryOp::Mul),
+            Token::Div => Some(BinaryOp::Div),
+            Token::Mod => Some(BinaryOp::Mod),
+            Token::Pow => Some(BinaryOp::Pow),
+            Token::BitAnd => Some(BinaryOp::BitAnd),
+            Token::BitOr => Some(BinaryOp::BitOr),
+            Token::BitXor => Some(BinaryOp::BitXor),
+            Token::Shl => Some(BinaryOp::Shl),
+            Token::Shr => Some(BinaryOp::Shr),
+            Token::Lt => Some(BinaryOp::Lt),
+            Token::Gt => Some(BinaryOp::Gt),
+            Token::Le => Some(BinaryOp::Le),
+            Token::Ge => Some(BinaryOp::Ge),
+            Token::Eq => Some(BinaryOp::Eq),
+            Token::Ne => Some(BinaryOp::Ne),
+            Token::And => Some(BinaryOp::And),
+            Token::Or => Some(BinaryOp::Or),
+            _ => None,
+        }
+    }
+
+    /// The token of this operator
+    pub fn token(&self) -> Token {
+        match self {
+            BinaryOp::Add => Token::Add,
+            BinaryOp::Sub => Token::Sub,
+            BinaryOp::Mul => Token::Mul,
+            BinaryOp::Div => Token::Div,
+            BinaryOp::Mod => Token::Mod,
+            BinaryOp::Pow => Token::Pow,
+            BinaryOp::BitAnd => Token::BitAnd,
+            BinaryOp::BitOr => Token::BitOr,
+            BinaryOp::BitXor => Token::BitXor,
+            BinaryOp::Shl => Token::Shl,
+            BinaryOp::Shr => Token::Shr,
+            BinaryOp::Lt => Token::Lt,
+            BinaryOp::Gt => Token::Gt,
+            BinaryOp::Le => Token::Le,
+            BinaryOp::Ge => Token::Ge,
+            BinaryOp::Eq => Token::Eq,
+            BinaryOp::Ne => Token::Ne,
+            BinaryOp::And => Token::And,
+            BinaryOp::Or => Token::Or,
+        }
+    }
+
+    /// Name of this operator in S-expressions
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Mod => "mod",
+            BinaryOp::Pow => "exp",
+            BinaryOp::BitAnd => "and",
+            BinaryOp::BitOr => "or",
+            BinaryOp::BitXor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "shr",
+            BinaryOp::Lt => "lt",
+            BinaryOp::Gt => "gt",
+            BinaryOp::Le => "le",
+            BinaryOp::Ge => "ge",
+            BinaryOp::Eq => "eq",
+            BinaryOp::Ne => "ne",
+            BinaryOp::And => "land",
+            BinaryOp::Or => "lor",
+        }
+    }
+}
+
+/// The kinds of expression
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ExprKind {
+    /// A number
+    Literal(Uint),
+    /// A named value
+    Variable(String),
+    /// A prefix operation
+    Unary { op: UnaryOp, expr: Box<Expr> },
+    /// An infix operation
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// A function call
+    Call { name: String, args: Vec<Expr> },
+    /// A name bound to a value in the scope of the body
+    Let {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// A conditional, `then` when the condition isn't 0 and `otherwise` when it is
+    If {
+        condition: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+}
+
+/// An expression with its location in the source
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    /// Build a prefix operation spanning its operand
+    pub fn unary(op: UnaryOp, expr: Expr) -> Expr {
+        let span = expr.span;
+        Expr::new(
+            ExprKind::Unary {
+                op,
+                expr: Box::new(expr),
+            },
+            span,
+        )
+    }
+
+    /// Build an infix operation spanning both operands
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        let span = lhs.span.to(rhs.span);
+        Expr::new(
+            ExprKind::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            span,
+        )
+    }
+
+    /// The same expression with another span
+    pub fn with_span(self, span: Span) -> Expr {
+        Expr { span, ..self }
+    }
+
+    /// Split a chain of infix operations nesting to the left, e.g. `1 - 2 - 3`,
+    /// into its first operand and the operations applied to it in order,
+    /// each one with its right operand
+    ///
+    /// Chains are as deep as they are long, so the passes over the tree walk them
+    /// with a loop and only recurse into the operands.
+    pub fn chain(&self) -> (&Expr, Vec<(&Expr, BinaryOp, &Expr)>) {
+        let mut operations = Vec::new();
+        let mut first = self;
+        while let ExprKind::Binary { op, lhs, rhs } = &first.kind {
+            operations.push((first, *op, rhs.as_ref()));
+            first = lhs;
+        }
+        operations.reverse();
+        (first, operations)
+    }
+
+    /// Write the expression as an S-expression,
+    /// e.g. `(add 156 (div (mul 4 3) 2))`
+    pub fn to_sexp(&self) -> String {
+        match &self.kind {
+            ExprKind::Literal(n) => n.to_string(),
+            ExprKind::Variable(name) => name.clone(),
+            ExprKind::Unary { op, expr } => format!("({} {})", op.name(), expr.to_sexp()),
+            ExprKind::Binary { .. } => {
+                let (first, operations) = self.chain();
+                let mut sexp = operations
+                    .iter()
+                    .rev()
+                    .map(|(_, op, _)| format!("({} ", op.name()))
+                    .collect::<String>();
+                sexp += &first.to_sexp();
+                for (_, _, rhs) in operations {
+                    sexp += &format!(" {})", rhs.to_sexp());
+                }
+                sexp
+            }
+            ExprKind::Call { name, args } => {
+                args.iter()
+                    .fold(format!("({}", name), |acc, a| acc + " " + &a.to_sexp())
+                    + ")"
+            }
+            ExprKind::Let { name, value, body } => {
+                format!("(let {} {} {})", name, value.to_sexp(), body.to_sexp())
+            }
+            ExprKind::If {
+                condition,
+                then,
+                otherwise,
+            } => format!(
+                "(if {} {} {})",
+                condition.to_sexp(),
+                then.to_sexp(),
+                otherwise.to_sexp()
+            ),
+        }
+    }
+
+    /// Number of levels of the expression, 1 for a number or a name
+    pub fn depth(&self) -> usize {
+        let children = match &self.kind {
+            ExprKind::Literal(_) | ExprKind::Variable(_) => 0,
+            ExprKind::Unary { expr, .. } => expr.depth(),
+            ExprKind::Binary { .. } => {
+                let (first, operations) = self.chain();
+                return operations.iter().fold(first.depth(), |depth, (_, _, rhs)| {
+                    depth.max(rhs.depth()) + 1
+                });
+            }
+            ExprKind::Call { args, .. } => args.iter().map(Expr::depth).max().unwrap_or(0),
+            ExprKind::Let { value, body, .. } => value.depth().max(body.depth()),
+            ExprKind::If {
+                condition,
+                then,
+                otherwise,
+            } => condition.depth().max(then.depth()).max(otherwise.depth()),
+        };
+        1 + children
+    }
+
+    /// Binding strength of the expression, used to decide where parentheses are needed
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExprKind::Unary { op, .. } => op.token().operator().map_or(u8::MAX, |o| o.precedence),
+            ExprKind::Binary { op, .. } => op.token().operator().map_or(u8::MAX, |o| o.precedence),
+            _ => u8::MAX,
+        }
+    }
+}
+
+/// Precedences the operands of `op` need to be written without parentheses,
+/// the operand on the non associative side needing parentheses on a tie
+fn operand_precedences(op: BinaryOp) -> (u8, u8) {
+    // Safe unwrap here, every infix operator has a precedence
+    let o = op.token().operator().unwrap();
+    match o.associativity {
+        Associativity::Right => (o.precedence + 1, o.precedence),
+        Associativity::Left => (o.precedence, o.precedence + 1),
+    }
+}
+
+/// Write `expr`, in parentheses if it binds looser than `precedence`
+fn write_operand(f: &mut std::fmt::Formatter<'_>, expr: &Expr, precedence: u8) -> std::fmt::Result {
+    // A let binding or a conditional extends as far as possible
+    if expr.precedence() < precedence
+        || matches!(expr.kind, ExprKind::Let { .. } | ExprKind::If { .. })
+    {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+/// Pretty-print the expression in infix notation with as few parentheses as possible
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ExprKind::Literal(n) => write!(f, "{}", n),
+            ExprKind::Variable(name) => write!(f, "{}", name),
+            ExprKind::Unary { op, expr } => {
+                write!(f, "{}", op.token())?;
+                write_operand(f, expr, self.precedence())
+            }
+            ExprKind::Binary { .. } => {
+                let (first, operations) = self.chain();
+                // Whether each operation is in parentheses as the left operand of the next one
+                let enclosed = operations
+                    .windows(2)
+                    .map(|w| w[0].0.precedence() < operand_precedences(w[1].1).0)
+                    .collect::<Vec<_>>();
+                write!(f, "{}", "(".repeat(enclosed.iter().filter(|&&e| e).count()))?;
+                write_operand(f, first, operand_precedences(operations[0].1).0)?;
+                for (i, (_, op, rhs)) in operations.iter().enumerate() {
+                    write!(f, " {} ", op.token())?;
+                    write_operand(f, rhs, operand_precedences(*op).1)?;
+                    if enclosed.get(i) == Some(&true) {
+                        write!(f, ")")?;
+                    }
+                }
+                Ok(())
+            }
+            ExprKind::Call { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", a)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Let { name, value, body } => {
+                write!(f, "let {} = ", name)?;
+                write_operand(f, value, 0)?;
+                write!(f, "; {}", body)
+            }
+            ExprKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                write!(f, "if ")?;
+                write_operand(f, condition, 0)?;
+                write!(f, " then ")?;
+                write_operand(f, then, 0)?;
+                write!(f, " else {}", otherwise)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // For unit testing in other modules
+    pub fn num(n: u64) -> Expr {
+        Expr::new(ExprKind::Literal(n.into()), Span::default())
+    }
+
+    // For unit testing in other modules
+    pub fn var(name: &str) -> Expr {
+        Expr::new(ExprKind::Variable(name.to_owned()), Span::default())
+    }
+
+    // For unit testing in other modules
+    pub fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::new(
+            ExprKind::Call {
+                name: name.to_owned(),
+                args,
+            },
+            Span::default(),
+        )
+    }
+
+    // For unit testing in other modules
+    pub fn let_(name: &str, value: Expr, body: Expr) -> Expr {
+        Expr::new(
+            ExprKind::Let {
+                name: name.to_owned(),
+                value: Box::new(value),
+                body: Box::new(body),
+            },
+            Span::default(),
+        )
+    }
+
+    // For unit testing in other modules
+    pub fn if_(condition: Expr, then: Expr, otherwise: Expr) -> Expr {
+        Expr::new(
+            ExprKind::If {
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            },
+            Span::default(),
+        )
+    }
+
+    #[test]
+    fn test_sexp() {
+        // 156 + 4 * 3 / 2
+        let e = Expr::binary(
+            BinaryOp::Add,
+            num(156),
+            Expr::binary(
+                BinaryOp::Div,
+                Expr::binary(BinaryOp::Mul, num(4), num(3)),
+                num(2),
+            ),
+        );
+        assert_eq!(e.to_sexp(), "(add 156 (div (mul 4 3) 2))");
+
+        let e = call(
+            "addmod",
+            vec![num(1), Expr::unary(UnaryOp::Neg, num(2)), num(3)],
+        );
+        assert_eq!(e.to_sexp(), "(addmod 1 (neg 2) 3)");
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        // (156 + 4) * 3 / 2
+        let e = Expr::binary(
+            BinaryOp::Div,
+            Expr::binary(
+                BinaryOp::Mul,
+                Expr::binary(BinaryOp::Add, num(156), num(4)),
+                num(3),
+            ),
+            num(2),
+        );
+        assert_eq!(e.to_string(), "(156 + 4) * 3 / 2");
+        let e = Expr::binary(
+            BinaryOp::Mul,
+            Expr::binary(BinaryOp::Sub, e, num(1)),
+            num(5),
+        );
+        assert_eq!(e.to_string(), "((156 + 4) * 3 / 2 - 1) * 5");
+        assert_eq!(e.to_sexp(), "(mul (sub (div (mul (add 156 4) 3) 2) 1) 5)");
+        assert_eq!(e.depth(), 6);
+
+        // 8 - (4 - 2)
+        let e = Expr::binary(
+            BinaryOp::Sub,
+            num(8),
+            Expr::binary(BinaryOp::Sub, num(4), num(2)),
+        );
+        assert_eq!(e.to_string(), "8 - (4 - 2)");
+
+        // (2 ** 3) ** 2
+        let e = Expr::binary(
+            BinaryOp::Pow,
+            Expr::binary(BinaryOp::Pow, num(2), num(3)),
+            num(2),
+        );
+        assert_eq!(e.to_string(), "(2 ** 3) ** 2");
+
+        // -(2 ** 2) and (-2) ** 2
+        let e = Expr::unary(UnaryOp::Neg, Expr::binary(BinaryOp::Pow, num(2), num(2)));
+        assert_eq!(e.to_string(), "-2 ** 2");
+        let e = Expr::binary(BinaryOp::Pow, Expr::unary(UnaryOp::Neg, num(2)), num(2));
+        assert_eq!(e.to_string(), "(-2) ** 2");
+
+        // ~(1 + x)
+        let e = Expr::unary(
+            UnaryOp::BitNot,
+            Expr::binary(BinaryOp::Add, num(1), var("x")),
+        );
+        assert_eq!(e.to_string(), "~(1 + x)");
+
+        let e = call("mulmod", vec![num(1), num(2), num(3)]);
+        assert_eq!(e.to_string(), "mulmod(1, 2, 3)");
+
+        // let x = 3 * 4; let y = (let z = 1; z); x + y
+        let e = let_(
+            "x",
+            Expr::binary(BinaryOp::Mul, num(3), num(4)),
+            let_(
+                "y",
+                let_("z", num(1), var("z")),
+                Expr::binary(BinaryOp::Add, var("x"), var("y")),
+            ),
+        );
+        assert_eq!(
+            e.to_string(),
+            "let x = 3 * 4; let y = (let z = 1; z); x + y"
+        );
+        assert_eq!(
+            e.to_sexp(),
+            "(let x (mul 3 4) (let y (let z 1 z) (add x y)))"
+        );
+        let e = Expr::binary(BinaryOp::Mul, num(2), let_("x", num(1), var("x")));
+        assert_eq!(e.to_string(), "2 * (let x = 1; x)");
+
+        // x > 1 && !(y == 2 | 1)
+        let e = Expr::binary(
+            BinaryOp::And,
+            Expr::binary(BinaryOp::Gt, var("x"), num(1)),
+            Expr::unary(
+                UnaryOp::Not,
+                Expr::binary(
+                    BinaryOp::Eq,
+                    var("y"),
+                    Expr::binary(BinaryOp::BitOr, num(2), num(1)),
+                ),
+            ),
+        );
+        assert_eq!(e.to_string(), "x > 1 && !(y == 2 | 1)");
+        assert_eq!(e.to_sexp(), "(land (gt x 1) (lnot (eq y (or 2 1))))");
+
+        // 1 + if x < 2 then (if y then 3 else 4) else 5 * 6
+        let e = Expr::binary(
+            BinaryOp::Add,
+            num(1),
+            if_(
+                Expr::binary(BinaryOp::Lt, var("x"), num(2)),
+                if_(var("y"), num(3), num(4)),
+                Expr::binary(BinaryOp::Mul, num(5), num(6)),
+            ),
+        );
+        assert_eq!(
+            e.to_string(),
+            "1 + (if x < 2 then (if y then 3 else 4) else 5 * 6)"
+        );
+        assert_eq!(e.to_sexp(), "(add 1 (if (lt x 2) (if y 3 4) (mul 5 6)))");
+    }
+}
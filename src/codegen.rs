@@ -1,90 +1,929 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::{Display, LowerHex, UpperHex},
+    str::FromStr,
+};
+
+use ethers_core::abi::Uint;
 
 use crate::{
-    parser::PNTokenList,
-    utils::{evm::Opcode, tokens::Token},
+    ast::{BinaryOp, Expr, ExprKind, UnaryOp},
+    optimizer,
+    utils::{
+        errors::{BytecodeError, CompileError},
+        evm::Opcode,
+        gas::Fork,
+        span::Span,
+    },
+    Options,
 };
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Bytecode(String);
+/// Raw EVM bytecode
+///
+/// Formats as uppercase hex by default, `{:x}` and `{:X}` pick the case and `#` adds the `0x` prefix.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Bytecode(Vec<u8>);
+
+impl From<Vec<u8>> for Bytecode {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytecode(bytes)
+    }
+}
+
+impl AsRef<[u8]> for Bytecode {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl LowerHex for Bytecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl UpperHex for Bytecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        self.0.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
 
 impl Display for Bytecode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{:X}", self)
+    }
+}
+
+/// Decode hexadecimal digits of either case, with an optional `0x` prefix
+impl FromStr for Bytecode {
+    type Err = BytecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let prefix = if s.starts_with("0x") || s.starts_with("0X") {
+            2
+        } else {
+            0
+        };
+        let digits = &s[prefix..];
+        if let Some((index, character)) =
+            digits.char_indices().find(|(_, c)| !c.is_ascii_hexdigit())
+        {
+            return Err(BytecodeError::InvalidCharacter {
+                character,
+                index: prefix + index,
+            });
+        }
+        if !digits.len().is_multiple_of(2) {
+            return Err(BytecodeError::OddLength(digits.len()));
+        }
+        let bytes = (0..digits.len())
+            .step_by(2)
+            // Safe unwrap here, the digits are all ASCII hexadecimal
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect();
+        Ok(Bytecode(bytes))
+    }
+}
+
+/// How numbers are interpreted
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Mode {
+    /// Numbers are uint256
+    #[default]
+    Unsigned,
+    /// Numbers are two's complement int256
+    Signed,
+}
+
+/// Builtin functions compiled to a single opcode, with their arity
+fn builtin(name: &str) -> Option<(Opcode, usize)> {
+    match name {
+        "addmod" => Some((Opcode::ADDMOD, 3)),
+        "mulmod" => Some((Opcode::MULMOD, 3)),
+        _ => None,
+    }
+}
+
+/// The opcodes of an operation whose operands are on the stack, the first one on top
+fn binary_opcodes(op: BinaryOp, mode: Mode) -> &'static [Opcode] {
+    match (op, mode) {
+        (BinaryOp::Add, _) => &[Opcode::ADD],
+        (BinaryOp::Sub, _) => &[Opcode::SUB],
+        (BinaryOp::Mul, _) => &[Opcode::MUL],
+        (BinaryOp::Div, Mode::Unsigned) => &[Opcode::DIV],
+        (BinaryOp::Div, Mode::Signed) => &[Opcode::SDIV],
+        (BinaryOp::Mod, Mode::Unsigned) => &[Opcode::MOD],
+        (BinaryOp::Mod, Mode::Signed) => &[Opcode::SMOD],
+        (BinaryOp::Pow, _) => &[Opcode::EXP],
+        (BinaryOp::BitAnd, _) => &[Opcode::AND],
+        (BinaryOp::BitOr, _) => &[Opcode::OR],
+        (BinaryOp::BitXor, _) => &[Opcode::XOR],
+        (BinaryOp::Shl, _) => &[Opcode::SHL],
+        (BinaryOp::Shr, Mode::Unsigned) => &[Opcode::SHR],
+        (BinaryOp::Shr, Mode::Signed) => &[Opcode::SAR],
+        (BinaryOp::Lt, Mode::Unsigned) => &[Opcode::LT],
+        (BinaryOp::Lt, Mode::Signed) => &[Opcode::SLT],
+        (BinaryOp::Gt, Mode::Unsigned) => &[Opcode::GT],
+        (BinaryOp::Gt, Mode::Signed) => &[Opcode::SGT],
+        // a <= b is !(a > b)
+        (BinaryOp::Le, Mode::Unsigned) => &[Opcode::GT, Opcode::ISZERO],
+        (BinaryOp::Le, Mode::Signed) => &[Opcode::SGT, Opcode::ISZERO],
+        (BinaryOp::Ge, Mode::Unsigned) => &[Opcode::LT, Opcode::ISZERO],
+        (BinaryOp::Ge, Mode::Signed) => &[Opcode::SLT, Opcode::ISZERO],
+        (BinaryOp::Eq, _) => &[Opcode::EQ],
+        (BinaryOp::Ne, _) => &[Opcode::EQ, Opcode::ISZERO],
+        // a && b is !(!a | !b)
+        (BinaryOp::And, _) => &[
+            Opcode::ISZERO,
+            Opcode::SWAP1,
+            Opcode::ISZERO,
+            Opcode::OR,
+            Opcode::ISZERO,
+        ],
+        // a || b is !!(a | b)
+        (BinaryOp::Or, _) => &[Opcode::OR, Opcode::ISZERO, Opcode::ISZERO],
+    }
+}
+
+/// The smallest PUSH instruction for `u`: PUSH0 for zero (EIP-3855) when the fork has it,
+/// PUSH1 to PUSH32 otherwise
+fn push(u: Uint, fork: Fork, code: &mut Vec<u8>) {
+    let size = u.bits().div_ceil(8).max(!fork.has_push0() as usize);
+    // Safe unwrap here, a Uint is at most 32 bytes long
+    code.push(Opcode::push(size).unwrap() as u8);
+    let mut word = [0u8; 32];
+    u.to_big_endian(&mut word);
+    code.extend_from_slice(&word[32 - size..]);
+}
+
+/// Whether the operands of an operation can be swapped
+fn is_commutative(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add
+            | BinaryOp::Mul
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+            | BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::And
+            | BinaryOp::Or
+    )
+}
+
+/// Largest number of stack slots the code of an expression uses at once,
+/// on top of the values below it
+fn stack_need(expr: &Expr) -> usize {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => 1,
+        // 0 - x
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            expr,
+        } => stack_need(expr).max(2),
+        ExprKind::Unary { expr, .. } => stack_need(expr),
+        ExprKind::Binary { .. } => {
+            let (first, operations) = expr.chain();
+            operations
+                .iter()
+                .fold(stack_need(first), |need, (_, _, rhs)| {
+                    binary_need(need, stack_need(rhs))
+                })
+        }
+        // The arguments are compiled from the last one
+        ExprKind::Call { args, .. } => args
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, arg)| i + stack_need(arg))
+            .max()
+            .unwrap_or(0),
+        ExprKind::Let { value, body, .. } => stack_need(value).max(1 + stack_need(body)),
+        // The jumps push their destination
+        ExprKind::If {
+            condition,
+            then,
+            otherwise,
+        } => stack_need(condition)
+            .max(stack_need(then))
+            .max(stack_need(otherwise))
+            .max(2),
+    }
+}
+
+/// Stack slots used by an infix operation whose operands use `a` and `b`:
+/// the first operand stays on the stack while the second one is calculated
+fn binary_need(a: usize, b: usize) -> usize {
+    a.max(b).max(a.min(b) + 1)
+}
+
+/// Size of the offsets of the jumps of conditionals, PUSH2
+const JUMP_SIZE: usize = 2;
+
+/// The code generator encapsulated in a struct.
+struct Codegen<'a> {
+    options: &'a Options,
+    code: Vec<u8>,
+    /// Variables in scope with the stack slot holding them, innermost last
+    scope: Vec<(&'a str, usize)>,
+    /// Values of the names that aren't bound in the expression
+    constants: &'a HashMap<String, Uint>,
+    /// Number of values the code leaves on the stack so far
+    depth: usize,
+}
+
+impl<'a> Codegen<'a> {
+    /// Append the bytecode leaving the value of `expr` on top of the stack
+    fn expression(&mut self, expr: &'a Expr) -> Result<(), CompileError> {
+        let depth = self.depth;
+        match &expr.kind {
+            ExprKind::Literal(u) => push(*u, self.options.fork, &mut self.code),
+            ExprKind::Variable(name) => match self.scope.iter().rev().find(|(n, _)| n == name) {
+                Some((_, slot)) => {
+                    let dup = Opcode::dup(depth - slot)
+                        .ok_or_else(|| CompileError::StackTooDeep(name.clone(), expr.span))?;
+                    self.code.push(dup as u8);
+                }
+                None => {
+                    let value = self
+                        .constants
+                        .get(name)
+                        .ok_or_else(|| CompileError::UndefinedVariable(name.clone(), expr.span))?;
+                    push(*value, self.options.fork, &mut self.code);
+                }
+            },
+            ExprKind::Unary { op, expr } => {
+                self.expression(expr)?;
+                match op {
+                    // 0 - x
+                    UnaryOp::Neg => {
+                        push(Uint::zero(), self.options.fork, &mut self.code);
+                        self.code.push(Opcode::SUB as u8);
+                    }
+                    UnaryOp::BitNot => self.code.push(Opcode::NOT as u8),
+                    UnaryOp::Not => self.code.push(Opcode::ISZERO as u8),
+                }
+            }
+            ExprKind::Binary { .. } => self.chain(expr)?,
+            ExprKind::Call { name, args } => {
+                let (opcode, arity) = builtin(name)
+                    .ok_or_else(|| CompileError::UnknownFunction(name.clone(), expr.span))?;
+                if args.len() != arity {
+                    return Err(CompileError::ArgumentCount {
+                        name: name.clone(),
+                        expected: arity,
+                        found: args.len(),
+                        span: expr.span,
+                    });
+                }
+                for arg in args.iter().rev() {
+                    self.expression(arg)?;
+                }
+                self.code.push(opcode as u8);
+            }
+            // The value stays on the stack below the body, then gets dropped
+            ExprKind::Let { name, value, body } => {
+                self.expression(value)?;
+                self.scope.push((name, depth));
+                self.expression(body)?;
+                self.scope.pop();
+                self.code.push(Opcode::SWAP1 as u8);
+                self.code.push(Opcode::POP as u8);
+            }
+            // condition PUSH2 then JUMPI otherwise PUSH2 end JUMP
+            // then: JUMPDEST then end: JUMPDEST
+            ExprKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expression(condition)?;
+                let to_then = self.jump(Opcode::JUMPI);
+                self.depth = depth;
+                self.expression(otherwise)?;
+                let to_end = self.jump(Opcode::JUMP);
+                self.depth = depth;
+                self.destination(to_then, expr.span)?;
+                self.expression(then)?;
+                self.destination(to_end, expr.span)?;
+            }
+        }
+        self.depth = depth + 1;
+        Ok(())
     }
+
+    /// Append the bytecode of a chain of infix operations, e.g. `x + x + x`
+    ///
+    /// The operand needing the most stack goes first (Sethi-Ullman), so that long chains
+    /// don't keep every operand on the stack. The chain is walked with a loop from the first
+    /// operation whose right operand goes first, or from its first operand.
+    fn chain(&mut self, expr: &'a Expr) -> Result<(), CompileError> {
+        let depth = self.depth;
+        let (first, operations) = expr.chain();
+        // Whether the left operand of each operation goes first
+        let mut need = stack_need(first);
+        let lhs_first = operations
+            .iter()
+            .map(|&(_, op, rhs)| {
+                let (lhs_need, rhs_need) = (need, stack_need(rhs));
+                need = binary_need(lhs_need, rhs_need);
+                // Ties keep the order the operation takes its operands in
+                match op {
+                    BinaryOp::Shl | BinaryOp::Shr => lhs_need >= rhs_need,
+                    _ => lhs_need > rhs_need,
+                }
+            })
+            .collect::<Vec<_>>();
+        let start = match lhs_first.iter().rposition(|&lhs_first| !lhs_first) {
+            Some(i) => {
+                let (_, op, rhs) = operations[i];
+                let lhs = match i {
+                    0 => first,
+                    _ => operations[i - 1].0,
+                };
+                self.expression(rhs)?;
+                self.expression(lhs)?;
+                self.operation(op, false);
+                self.depth = depth + 1;
+                i + 1
+            }
+            None => {
+                self.expression(first)?;
+                0
+            }
+        };
+        for &(_, op, rhs) in &operations[start..] {
+            self.expression(rhs)?;
+            self.operation(op, true);
+            self.depth = depth + 1;
+        }
+        Ok(())
+    }
+
+    /// Append the opcodes of an infix operation, after the code of its operands
+    ///
+    /// Shifts take the shift amount on top of the stack, other operations take
+    /// their first operand on top, so operands the other way round get swapped.
+    fn operation(&mut self, op: BinaryOp, lhs_first: bool) {
+        let shift = matches!(op, BinaryOp::Shl | BinaryOp::Shr);
+        if lhs_first != shift && !is_commutative(op) {
+            self.code.push(Opcode::SWAP1 as u8);
+        }
+        let opcodes = binary_opcodes(op, self.options.mode);
+        self.code.extend(opcodes.iter().map(|&opcode| opcode as u8));
+    }
+
+    /// Append a jump to a destination that isn't known yet,
+    /// and return the position of the offset to patch
+    fn jump(&mut self, opcode: Opcode) -> usize {
+        self.code.push(Opcode::PUSH2 as u8);
+        let position = self.code.len();
+        self.code.extend([0; JUMP_SIZE]);
+        self.code.push(opcode as u8);
+        position
+    }
+
+    /// Append a JUMPDEST and patch the jump at `position` to land on it
+    fn destination(&mut self, position: usize, span: Span) -> Result<(), CompileError> {
+        let offset = self.code.len();
+        let offset_bytes = u16::try_from(offset)
+            .map_err(|_| CompileError::CodeTooLarge(offset, span))?
+            .to_be_bytes();
+        self.code[position..position + JUMP_SIZE].copy_from_slice(&offset_bytes);
+        self.code.push(Opcode::JUMPDEST as u8);
+        Ok(())
+    }
+}
+
+/// Compile an expression for the given mode and fork
+pub fn compile(expr: &Expr, options: &Options) -> Result<Bytecode, CompileError> {
+    compile_with_constants(expr, options, &HashMap::new())
+}
+
+/// Compile an expression whose free names are replaced with the given values
+pub fn compile_with_constants(
+    expr: &Expr,
+    options: &Options,
+    constants: &HashMap<String, Uint>,
+) -> Result<Bytecode, CompileError> {
+    let optimized;
+    let expr = if options.optimize {
+        // The values of the names get calculated with too
+        optimized = optimizer::optimize(&optimizer::substitute(expr, constants), options);
+        &optimized
+    } else {
+        expr
+    };
+    let mut codegen = Codegen {
+        options,
+        code: Vec::new(),
+        scope: Vec::new(),
+        constants,
+        depth: 0,
+    };
+    codegen.expression(expr)?;
+    let bytecode = Bytecode(codegen.code);
+    Ok(if options.optimize {
+        optimizer::peephole(&bytecode)
+    } else {
+        bytecode
+    })
 }
 
-fn generate_bytecode(token: &Token) -> String {
-    match token {
-        &Token::Num(u) => format!("{}{:064X}", Opcode::PUSH32, u),
-        Token::Add => Opcode::ADD.to_string(),
-        Token::Mul => Opcode::MUL.to_string(),
-        Token::Sub => Opcode::SUB.to_string(),
-        Token::Div => Opcode::DIV.to_string(),
-        _ => unreachable!(),
+/// Deployable code of a calculation
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Contract {
+    /// Code returning the result as a 32-byte word
+    pub runtime: Bytecode,
+    /// Constructor code returning the runtime code, what gets deployed
+    pub init: Bytecode,
+}
+
+impl Contract {
+    /// Wrap the bytecode of a calculation into a contract returning its result
+    pub fn new(calculation: &Bytecode, fork: Fork) -> Self {
+        // MSTORE(0, result) RETURN(0, 32)
+        let mut runtime = calculation.0.clone();
+        push(Uint::zero(), fork, &mut runtime);
+        runtime.push(Opcode::MSTORE as u8);
+        push(Uint::from(32), fork, &mut runtime);
+        push(Uint::zero(), fork, &mut runtime);
+        runtime.push(Opcode::RETURN as u8);
+
+        // CODECOPY(0, offset, size) RETURN(0, size), the runtime code starting at `offset`
+        let size = Uint::from(runtime.len());
+        let constructor = |offset: usize| {
+            let mut code = Vec::new();
+            push(size, fork, &mut code);
+            push(Uint::from(offset), fork, &mut code);
+            push(Uint::zero(), fork, &mut code);
+            code.push(Opcode::CODECOPY as u8);
+            push(size, fork, &mut code);
+            push(Uint::zero(), fork, &mut code);
+            code.push(Opcode::RETURN as u8);
+            code
+        };
+        // The offset is the size of the constructor, which depends on the size of the offset
+        let mut offset = 0;
+        let mut init = loop {
+            let code = constructor(offset);
+            if code.len() == offset {
+                break code;
+            }
+            offset = code.len();
+        };
+        init.extend_from_slice(&runtime);
+
+        Contract {
+            runtime: Bytecode(runtime),
+            init: Bytecode(init),
+        }
     }
 }
 
 // 156 + 4 * 3 / 2
-// + 156 / * 4 3 2   <-- PN
-// (add 156 (div (mul 4 3) 2))
-// PUSH1 2 PUSH1 3 PUSH 4 MUL DIV
+// (add 156 (div (mul 4 3) 2))   <-- AST
+// PUSH1 3 PUSH1 4 MUL PUSH1 2 SWAP1 DIV
 // PUSH1 156 ADD
-impl From<PNTokenList> for Bytecode {
-    fn from(tokens: PNTokenList) -> Self {
-        let bytecode = tokens
-            .into_iter()
-            .rev()
-            .map(|t| generate_bytecode(&t))
-            .collect();
-        Bytecode(bytecode)
+impl TryFrom<&Expr> for Bytecode {
+    type Error = CompileError;
+
+    fn try_from(expr: &Expr) -> Result<Self, Self::Error> {
+        compile(expr, &Options::default())
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::parser::tests::pn_token_list;
+    use crate::{
+        ast::tests::{call, if_, let_, num, var},
+        utils::evm::{Outcome, EVM},
+    };
 
+    // For unit testing in other modules
     pub fn bytecode(s: String) -> Bytecode {
-        Bytecode(s)
+        s.parse().unwrap()
     }
 
     #[test]
     fn test_bytecode_conversion() {
         // 156 + 4 * 3 / 2
-        // + 156 / * 4 3 2
-        let pn = pn_token_list(
-            vec![
-                Token::Add,
-                Token::Num(156u8.into()),
-                Token::Div,
-                Token::Mul,
-                Token::Num(4u8.into()),
-                Token::Num(3u8.into()),
-                Token::Num(2u8.into()),
-            ]
-            .into(),
-        );
         // (add 156 (div (mul 4 3) 2))
-        // PUSH 2 PUSH 3 PUSH 4 MUL DIV
+        let ast = Expr::binary(
+            BinaryOp::Add,
+            num(156),
+            Expr::binary(
+                BinaryOp::Div,
+                Expr::binary(BinaryOp::Mul, num(4), num(3)),
+                num(2),
+            ),
+        );
+        // PUSH 3 PUSH 4 MUL PUSH 2 SWAP1 DIV
         // PUSH 156 ADD
-        let expected = Bytecode(
-            vec![
-                "7F",                                                               // PUSH32
-                "0000000000000000000000000000000000000000000000000000000000000002", // 2
-                "7F",                                                               // PUSH32
-                "0000000000000000000000000000000000000000000000000000000000000003", // 3
-                "7F",                                                               // PUSH32
-                "0000000000000000000000000000000000000000000000000000000000000004", // 4
-                "02",                                                               // MUL
-                "04",                                                               // DIV
-                "7F",                                                               // PUSH32
-                "000000000000000000000000000000000000000000000000000000000000009C", // 156
-                "01",                                                               // ADD
+        let expected = bytecode(
+            [
+                "60", "03", // PUSH1 3
+                "60", "04", // PUSH1 4
+                "02", // MUL
+                "60", "02", // PUSH1 2
+                "90", // SWAP1
+                "04", // DIV
+                "60", "9C", // PUSH1 156
+                "01", // ADD
             ]
             .join(""),
         );
-        assert_eq!(Bytecode::from(pn), expected);
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+    }
+
+    #[test]
+    fn test_signed_bytecode_conversion() {
+        // -4 / 2
+        // (div (neg 4) 2)
+        let ast = Expr::binary(BinaryOp::Div, Expr::unary(UnaryOp::Neg, num(4)), num(2));
+        // PUSH 4 PUSH 0 SUB PUSH 2 SWAP1 SDIV
+        let expected = bytecode(
+            [
+                "60", "04", // PUSH1 4
+                "5F", // PUSH0
+                "03", // SUB
+                "60", "02", // PUSH1 2
+                "90", // SWAP1
+                "05", // SDIV
+            ]
+            .join(""),
+        );
+        let options = Options {
+            mode: Mode::Signed,
+            ..Options::default()
+        };
+        assert_eq!(compile(&ast, &options), Ok(expected));
+    }
+
+    #[test]
+    fn test_shift_bytecode_conversion() {
+        // 1 << 4
+        let ast = Expr::binary(BinaryOp::Shl, num(1), num(4));
+        // PUSH 1 PUSH 4 SHL
+        let expected = bytecode(
+            [
+                "60", "01", // PUSH1 1
+                "60", "04", // PUSH1 4
+                "1B", // SHL
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+    }
+
+    #[test]
+    fn test_call_bytecode_conversion() {
+        // addmod(1, 2, 3)
+        let ast = call("addmod", vec![num(1), num(2), num(3)]);
+        // PUSH 3 PUSH 2 PUSH 1 ADDMOD
+        let expected = bytecode(
+            [
+                "60", "03", // PUSH1 3
+                "60", "02", // PUSH1 2
+                "60", "01", // PUSH1 1
+                "08", // ADDMOD
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+
+        let ast = call("mulmod", vec![num(1)]);
+        assert_eq!(
+            Bytecode::try_from(&ast),
+            Err(CompileError::ArgumentCount {
+                name: "mulmod".to_owned(),
+                expected: 3,
+                found: 1,
+                span: Span::default()
+            })
+        );
+        let ast = call("sqrt", vec![num(1)]);
+        assert_eq!(
+            Bytecode::try_from(&ast),
+            Err(CompileError::UnknownFunction(
+                "sqrt".to_owned(),
+                Span::default()
+            ))
+        );
+        let ast = var("x");
+        assert_eq!(
+            Bytecode::try_from(&ast),
+            Err(CompileError::UndefinedVariable(
+                "x".to_owned(),
+                Span::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_let_bytecode_conversion() {
+        // let x = 3; let y = 4; x * y
+        let ast = let_(
+            "x",
+            num(3),
+            let_("y", num(4), Expr::binary(BinaryOp::Mul, var("x"), var("y"))),
+        );
+        let expected = bytecode(
+            [
+                "60", "03", // PUSH1 3
+                "60", "04", // PUSH1 4
+                "80", // DUP1 (y)
+                "82", // DUP3 (x)
+                "02", // MUL
+                "90", "50", // SWAP1 POP (y)
+                "90", "50", // SWAP1 POP (x)
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+
+        // Shadowing
+        let ast = let_("x", num(1), let_("x", num(2), var("x")));
+        assert_eq!(
+            Bytecode::try_from(&ast),
+            Ok(bytecode("600160028090509050".to_owned()))
+        );
+
+        // Constants are pushed, unless a binding shadows them
+        let constants = HashMap::from([("x".to_owned(), Uint::from(7))]);
+        let ast = Expr::binary(BinaryOp::Add, let_("x", num(1), var("x")), var("x"));
+        assert_eq!(
+            compile_with_constants(&ast, &Options::default(), &constants),
+            Ok(bytecode("6001809050600701".to_owned()))
+        );
+
+        // Out of the scope
+        let ast = Expr::binary(BinaryOp::Add, let_("x", num(1), var("x")), var("x"));
+        assert_eq!(
+            Bytecode::try_from(&ast),
+            Err(CompileError::UndefinedVariable(
+                "x".to_owned(),
+                Span::default()
+            ))
+        );
+
+        // DUP16 reaches 15 values above the variable
+        let deep = |n: u64| (0..n).fold(var("x"), |body, i| let_(&format!("y{}", i), num(i), body));
+        assert!(Bytecode::try_from(&let_("x", num(1), deep(15))).is_ok());
+        assert_eq!(
+            Bytecode::try_from(&let_("x", num(1), deep(16))),
+            Err(CompileError::StackTooDeep("x".to_owned(), Span::default()))
+        );
+
+        // The longer operand goes first, so chains don't pile up on the stack
+        // let x = 3; x + x + ... + x
+        let chain = |op: BinaryOp, n: u64| {
+            let sum = (1..n).fold(var("x"), |e, _| Expr::binary(op, e, var("x")));
+            let_("x", num(3), sum)
+        };
+        let run = |ast: &Expr| {
+            EVM::default()
+                .execute(&Bytecode::try_from(ast).unwrap())
+                .and_then(|outcome| outcome.value())
+        };
+        assert_eq!(run(&chain(BinaryOp::Add, 100)), Ok(Uint::from(300)));
+        // let x = 3; x - x - ... - x, which swaps its operands back
+        assert_eq!(
+            run(&chain(BinaryOp::Sub, 20)),
+            Ok(Uint::MAX - Uint::from(53))
+        );
+        // x - (x - 1) keeps its order
+        let ast = let_(
+            "x",
+            num(3),
+            Expr::binary(
+                BinaryOp::Sub,
+                var("x"),
+                Expr::binary(BinaryOp::Sub, var("x"), num(1)),
+            ),
+        );
+        assert_eq!(run(&ast), Ok(Uint::one()));
+    }
+
+    #[test]
+    fn test_condition_bytecode_conversion() {
+        // 1 <= 2
+        let ast = Expr::binary(BinaryOp::Le, num(1), num(2));
+        let expected = bytecode(
+            [
+                "60", "02", // PUSH1 2
+                "60", "01", // PUSH1 1
+                "11", "15", // GT ISZERO
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+        let signed = Options {
+            mode: Mode::Signed,
+            ..Options::default()
+        };
+        assert_eq!(
+            compile(&ast, &signed),
+            Ok(bytecode("600260011315".to_owned()))
+        );
+
+        // 1 && 2, !3
+        let ast = Expr::binary(BinaryOp::And, num(1), num(2));
+        let expected = bytecode(
+            [
+                "60", "02", // PUSH1 2
+                "60", "01", // PUSH1 1
+                "15", // ISZERO
+                "90", // SWAP1
+                "15", // ISZERO
+                "17", // OR
+                "15", // ISZERO
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+        let ast = Expr::unary(UnaryOp::Not, num(3));
+        let expected = bytecode(
+            [
+                "60", "03", // PUSH1 3
+                "15", // ISZERO
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+
+        // if 1 then 2 else 3
+        let ast = if_(num(1), num(2), num(3));
+        let expected = bytecode(
+            [
+                "60", "01", // PUSH1 1
+                "61", "000C", "57", // PUSH2 then JUMPI
+                "60", "03", // PUSH1 3
+                "61", "000F", "56", // PUSH2 end JUMP
+                "5B", "60", "02", // then: JUMPDEST PUSH1 2
+                "5B", // end: JUMPDEST
+            ]
+            .join(""),
+        );
+        assert_eq!(Bytecode::try_from(&ast), Ok(expected));
+
+        // Each branch starts from the same stack
+        // let x = 5; if x > 4 then (let y = 1; x + y) else x
+        let ast = let_(
+            "x",
+            num(5),
+            if_(
+                Expr::binary(BinaryOp::Gt, var("x"), num(4)),
+                let_("y", num(1), Expr::binary(BinaryOp::Add, var("x"), var("y"))),
+                var("x"),
+            ),
+        );
+        let run = |ast: &Expr| {
+            EVM::default()
+                .execute(&Bytecode::try_from(ast).unwrap())
+                .and_then(|outcome| outcome.value())
+        };
+        assert_eq!(run(&ast), Ok(Uint::from(6)));
+        let ast = let_(
+            "x",
+            num(3),
+            if_(
+                Expr::binary(BinaryOp::Gt, var("x"), num(4)),
+                num(1),
+                var("x"),
+            ),
+        );
+        assert_eq!(run(&ast), Ok(Uint::from(3)));
+        assert!(matches!(
+            EVM::default().execute(&Bytecode::try_from(&ast).unwrap()),
+            Ok(Outcome::Stop(_))
+        ));
+
+        // Jumps only reach the first 64 KiB of code
+        fn sum(n: u32) -> Expr {
+            match n {
+                0 => num(1),
+                _ => Expr::binary(BinaryOp::Add, sum(n - 1), sum(n - 1)),
+            }
+        }
+        let big = sum(15);
+        assert_eq!(
+            Bytecode::try_from(&if_(num(1), num(2), big)),
+            Err(CompileError::CodeTooLarge(98_313, Span::default()))
+        );
+    }
+
+    #[test]
+    fn test_contract() {
+        // 1 + 2
+        let calculation = bytecode("6002600101".to_owned());
+        let contract = Contract::new(&calculation, Fork::Cancun);
+        let runtime = [
+            "6002600101", // 1 + 2
+            "5F",
+            "52", // PUSH0 MSTORE
+            "6020",
+            "5F",
+            "F3", // PUSH1 32 PUSH0 RETURN
+        ]
+        .join("");
+        assert_eq!(contract.runtime, bytecode(runtime.clone()));
+        let init = [
+            "600B", "600A", "5F", "39", // PUSH1 11 PUSH1 10 PUSH0 CODECOPY
+            "600B", "5F", "F3", // PUSH1 11 PUSH0 RETURN
+        ]
+        .join("");
+        assert_eq!(contract.init, bytecode(init + &runtime));
+
+        // No PUSH0 before Shanghai
+        let contract = Contract::new(&calculation, Fork::Berlin);
+        assert_eq!(
+            contract.runtime,
+            bytecode("600260010160005260206000F3".to_owned())
+        );
+        assert_eq!(&contract.init.as_ref()[..4], &[0x60, 0x0D, 0x60, 0x0C]);
+
+        // The offset of the runtime code takes a PUSH2 past 255 bytes
+        let calculation = Bytecode::from(vec![Opcode::PUSH0 as u8; 300]);
+        let contract = Contract::new(&calculation, Fork::Cancun);
+        let init = contract.init.as_ref();
+        let offset = init.len() - contract.runtime.as_ref().len();
+        assert_eq!(offset, 12);
+        assert_eq!(&init[..6], &[0x61, 0x01, 0x32, 0x60, 0x0C, 0x5F]);
+        assert_eq!(&init[offset..], contract.runtime.as_ref());
+    }
+
+    #[test]
+    fn test_hex() {
+        let b = Bytecode::from(vec![0x60, 0xab, 0x5f]);
+        assert_eq!(b.to_string(), "60AB5F");
+        assert_eq!(format!("{:x}", b), "60ab5f");
+        assert_eq!(format!("{:#x}", b), "0x60ab5f");
+        assert_eq!(format!("{:#X}", b), "0x60AB5F");
+        assert_eq!(b.as_ref(), &[0x60, 0xab, 0x5f]);
+
+        assert_eq!("60ab5F".parse(), Ok(b.clone()));
+        assert_eq!("0x60AB5f".parse(), Ok(b.clone()));
+        assert_eq!("0X60ab5f".parse(), Ok(b.clone()));
+        assert_eq!("".parse(), Ok(Bytecode::default()));
+        assert_eq!("0x".parse(), Ok(Bytecode::default()));
+        assert_eq!(
+            "0x60a".parse::<Bytecode>(),
+            Err(BytecodeError::OddLength(3))
+        );
+        assert_eq!(
+            "0x6g".parse::<Bytecode>(),
+            Err(BytecodeError::InvalidCharacter {
+                character: 'g',
+                index: 3
+            })
+        );
+        assert_eq!(
+            "60 01".parse::<Bytecode>(),
+            Err(BytecodeError::InvalidCharacter {
+                character: ' ',
+                index: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_minimal_push() {
+        let push = |n: Uint| Bytecode::try_from(&Expr::new(ExprKind::Literal(n), Span::default()));
+        assert_eq!(push(Uint::zero()), Ok(bytecode("5F".to_owned())));
+        assert_eq!(push(Uint::one()), Ok(bytecode("6001".to_owned())));
+        assert_eq!(push(Uint::from(255)), Ok(bytecode("60FF".to_owned())));
+        assert_eq!(push(Uint::from(256)), Ok(bytecode("610100".to_owned())));
+        assert_eq!(
+            push(Uint::from(0x10000)),
+            Ok(bytecode("62010000".to_owned()))
+        );
+        assert_eq!(
+            push(Uint::one() << 248),
+            Ok(bytecode(format!("7F01{}", "0".repeat(62))))
+        );
+        assert_eq!(
+            push(Uint::MAX),
+            Ok(bytecode(format!("7F{}", "F".repeat(64))))
+        );
+
+        // No PUSH0 before Shanghai
+        let options = Options {
+            fork: Fork::Berlin,
+            ..Options::default()
+        };
+        let ast = Expr::unary(UnaryOp::Neg, num(0));
+        assert_eq!(
+            compile(&ast, &options),
+            Ok(bytecode("6000600003".to_owned()))
+        );
     }
 }
@@ -1,18 +1,27 @@
-use std::{fmt::Display, iter::Peekable, ops::Index, slice::SliceIndex, str::Chars};
+use std::{fmt::Display, iter::Peekable, ops::Index, slice::SliceIndex, str::CharIndices};
 
 use ethers_core::abi::Uint;
 
-use crate::utils::{errors::LexicalError, tokens::Token};
+use crate::utils::{
+    errors::LexicalError,
+    span::{Span, Spanned},
+    tokens::Token,
+};
 
 /// A valid list of tokens
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct TokenList(Vec<Token>);
+pub struct TokenList(Vec<Spanned<Token>>);
 
 impl TokenList {
-    /// Public function to get the list length
+    /// Number of tokens
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// Whether there are no tokens
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl Display for TokenList {
@@ -22,6 +31,8 @@ impl Display for TokenList {
             "{}",
             self.0
                 .iter()
+                // Whitespaces are written as the separators already
+                .filter(|t| t.node != Token::Whitespace)
                 .map(|t| t.to_string())
                 .collect::<Vec<String>>()
                 .join(" ")
@@ -30,7 +41,7 @@ impl Display for TokenList {
 }
 
 impl IntoIterator for TokenList {
-    type Item = Token;
+    type Item = Spanned<Token>;
     type IntoIter = std::vec::IntoIter<Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -40,7 +51,7 @@ impl IntoIterator for TokenList {
 
 impl<Idx> Index<Idx> for TokenList
 where
-    Idx: SliceIndex<[Token]>,
+    Idx: SliceIndex<[Spanned<Token>]>,
 {
     type Output = Idx::Output;
 
@@ -51,8 +62,8 @@ where
 
 /// The lexer encapsulated in a struct.
 pub struct Lexer<'a> {
-    /// The source code as peekable chars.
-    pub chars: Peekable<Chars<'a>>,
+    /// The source code as peekable chars with their byte offsets.
+    pub chars: Peekable<CharIndices<'a>>,
     /// The raw source code.
     pub source: &'a str,
 }
@@ -62,50 +73,279 @@ pub fn lexer(source: &str) -> Result<TokenList, LexicalError> {
     // https://doc.rust-lang.org/stable/rust-by-example/error/iter_result.html#fail-the-entire-operation-with-collect
     let lexer = Lexer {
         source,
-        chars: source.chars().peekable(),
+        chars: source.char_indices().peekable(),
     };
     lexer
         .into_iter()
-        .collect::<Result<Vec<Token>, LexicalError>>()
+        .collect::<Result<Vec<Spanned<Token>>, LexicalError>>()
         .map(|v| v.into())
 }
 
-impl<'a> Iterator for Lexer<'a> {
-    type Item = Result<Token, LexicalError>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let next = self.chars.next();
-
-        if next.is_none() {
-            return None;
+impl<'a> Lexer<'a> {
+    /// Push the name characters that follow to `s`
+    fn word(&mut self, s: &mut String) {
+        while let Some((_, d)) = self.chars.next_if(|&(_, d)| is_identifier(d)) {
+            s.push(d);
         }
+    }
+
+    /// Span from `start` to the next char
+    fn span_from(&mut self, start: usize) -> Span {
+        let end = self
+            .chars
+            .peek()
+            .map_or(self.source.len(), |&(offset, _)| offset);
+        Span::new(start, end)
+    }
 
-        // Safe unwrap here
-        let c = next.unwrap();
-        Some(match c {
+    /// Read the token starting with `c` at offset `start`
+    fn token(&mut self, start: usize, c: char) -> Result<Token, LexicalError> {
+        match c {
             '(' => Ok(Token::OpenParen),
             ')' => Ok(Token::CloseParen),
             '+' => Ok(Token::Add),
             '-' => Ok(Token::Sub),
-            '*' => Ok(Token::Mul),
+            '*' => Ok(match self.chars.next_if(|&(_, d)| d == '*') {
+                Some(_) => Token::Pow,
+                None => Token::Mul,
+            }),
             '/' => Ok(Token::Div),
-            '0'..='9' => {
+            '%' => Ok(Token::Mod),
+            '&' => Ok(match self.chars.next_if(|&(_, d)| d == '&') {
+                Some(_) => Token::And,
+                None => Token::BitAnd,
+            }),
+            '|' => Ok(match self.chars.next_if(|&(_, d)| d == '|') {
+                Some(_) => Token::Or,
+                None => Token::BitOr,
+            }),
+            '^' => Ok(Token::BitXor),
+            '~' => Ok(Token::BitNot),
+            '<' | '>' => match (c, self.chars.next_if(|&(_, d)| d == c || d == '=')) {
+                ('<', Some((_, '<'))) => Ok(Token::Shl),
+                ('>', Some((_, '>'))) => Ok(Token::Shr),
+                ('<', Some(_)) => Ok(Token::Le),
+                (_, Some(_)) => Ok(Token::Ge),
+                ('<', None) => Ok(Token::Lt),
+                (_, None) => Ok(Token::Gt),
+            },
+            '!' => Ok(match self.chars.next_if(|&(_, d)| d == '=') {
+                Some(_) => Token::Ne,
+                None => Token::Not,
+            }),
+            '?' => Ok(Token::Question),
+            ':' => Ok(Token::Colon),
+            ',' => Ok(Token::Comma),
+            // History references, e.g. `$1`
+            '$' => {
                 let mut s = c.to_string();
-                while let Some(d) = self.chars.next_if(|d| d.is_numeric()) {
+                while let Some((_, d)) = self.chars.next_if(|(_, d)| d.is_ascii_digit()) {
                     s.push(d);
                 }
-                Uint::from_dec_str(&s)
+                match s.len() {
+                    1 => Err(LexicalError::InvalidCharacter(c, self.span_from(start))),
+                    _ => Ok(Token::Ident(s)),
+                }
+            }
+            '=' => Ok(match self.chars.next_if(|&(_, d)| d == '=') {
+                Some(_) => Token::Eq,
+                None => Token::Assign,
+            }),
+            ';' => Ok(Token::Semicolon),
+            '0'..='9' => {
+                // Read the whole word so that a number can't run into a name, e.g. `1e`
+                let mut s = c.to_string();
+                self.word(&mut s);
+                // Decimals, e.g. `1.5`
+                let mut ahead = self.chars.clone();
+                if ahead.next().is_some_and(|(_, d)| d == '.')
+                    && ahead.peek().is_some_and(|(_, d)| d.is_ascii_digit())
+                {
+                    self.chars = ahead;
+                    s.push('.');
+                    self.word(&mut s);
+                }
+                // A unit after whitespace, e.g. `20 gwei`
+                if !UNITS.iter().any(|(unit, _)| s.ends_with(unit)) {
+                    let mut ahead = self.chars.clone();
+                    let mut unit = String::new();
+                    while let Some((_, d)) = ahead.next_if(|&(_, d)| d.is_whitespace()) {
+                        unit.push(d);
+                    }
+                    let spaces = unit.len();
+                    while let Some((_, d)) = ahead.next_if(|&(_, d)| is_identifier(d)) {
+                        unit.push(d);
+                    }
+                    if spaces > 0 && UNITS.iter().any(|(u, _)| *u == &unit[spaces..]) {
+                        self.chars = ahead;
+                        s.push_str(&unit);
+                    }
+                }
+                number(&s)
                     .map(Token::Num)
-                    .map_err(move |_e| LexicalError::InvalidNumber(s))
+                    .map_err(|e| LexicalError::InvalidNumber(s, e, self.span_from(start)))
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let mut s = c.to_string();
+                while let Some((_, d)) = self.chars.next_if(|&(_, d)| is_identifier(d)) {
+                    s.push(d);
+                }
+                match s.as_str() {
+                    "let" => Ok(Token::Let),
+                    "if" => Ok(Token::If),
+                    "then" => Ok(Token::Then),
+                    "else" => Ok(Token::Else),
+                    _ => Ok(Token::Ident(s)),
+                }
             }
             _ if c.is_whitespace() => Ok(Token::Whitespace),
-            _ => Err(LexicalError::InvalidCharacter(c)),
-        })
+            _ => Err(LexicalError::InvalidCharacter(c, self.span_from(start))),
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Spanned<Token>, LexicalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, c) = self.chars.next()?;
+        Some(
+            self.token(start, c)
+                .map(|t| Spanned::new(t, self.span_from(start))),
+        )
+    }
+}
+
+/// Whether a character can be part of a name
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Ethereum units with their number of decimals, longest names first
+/// so that `gwei` isn't read as `wei`
+pub const UNITS: [(&str, u32); 5] = [
+    ("finney", 15),
+    ("ether", 18),
+    ("szabo", 12),
+    ("gwei", 9),
+    ("wei", 0),
+];
+
+/// Why a number literal is invalid
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum NumberError {
+    /// Not a number, e.g. `0x`, `1e` or `1__0`
+    Malformed,
+    /// Larger than 2^256 - 1
+    Overflow,
+    /// Not a whole number of wei, e.g. `1.5 wei`
+    Fractional,
+    /// A unit after a hexadecimal, binary or octal number, e.g. `0x10 ether`
+    UnitWithPrefix,
+}
+
+/// Parse a number literal:
+///
+/// - decimal, or hexadecimal, binary and octal with a `0x`, `0b` or `0o` prefix
+/// - with `_` allowed between digits
+/// - decimals with `.` and scientific notation with `e` e.g. `1.5e18`
+/// - a unit scaling a decimal number to wei, e.g. `20 gwei` or `1.5ether`
+pub fn number(literal: &str) -> Result<Uint, NumberError> {
+    let unit = UNITS.iter().find(|(unit, _)| literal.ends_with(unit));
+    let (literal, decimals) = match unit {
+        Some((unit, decimals)) => (literal[..literal.len() - unit.len()].trim_end(), *decimals),
+        None => (literal, 0),
+    };
+
+    let prefix = literal.get(..2).map(|p| p.to_ascii_lowercase());
+    let radix = match prefix.as_deref() {
+        Some("0x") => 16,
+        Some("0b") => 2,
+        Some("0o") => 8,
+        _ => 10,
+    };
+    if radix != 10 {
+        let value = digits(&literal[2..], radix)?;
+        return match unit {
+            Some(_) => Err(NumberError::UnitWithPrefix),
+            None => Ok(value),
+        };
+    }
+
+    let (mantissa, exponent) = match literal.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => {
+            if exponent.is_empty() || !exponent.chars().all(|c| c.is_ascii_digit()) {
+                return Err(NumberError::Malformed);
+            }
+            // Too large for any number but zero anyway
+            (mantissa, exponent.parse::<u32>().unwrap_or(u32::MAX))
+        }
+        None => (literal, 0),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    digits(integer, 10)?;
+    let fraction = match fraction {
+        Some(fraction) => {
+            digits(fraction, 10)?;
+            fraction.replace('_', "")
+        }
+        None => String::new(),
+    };
+
+    // Scale the digits without the point by the exponent, the unit and the point
+    let mut all = integer.replace('_', "") + &fraction;
+    let shift = i64::from(exponent) + i64::from(decimals) - fraction.len() as i64;
+    if shift < 0 {
+        let kept = all.len().saturating_sub(shift.unsigned_abs() as usize);
+        if all[kept..].chars().any(|c| c != '0') {
+            return Err(NumberError::Fractional);
+        }
+        all.truncate(kept);
+    }
+    match all.as_str() {
+        "" => Ok(Uint::zero()),
+        _ => scale(digits(&all, 10)?, shift.max(0) as u64),
+    }
+}
+
+/// Multiply `value` by 10^`exponent`
+fn scale(value: Uint, exponent: u64) -> Result<Uint, NumberError> {
+    if value.is_zero() {
+        return Ok(value);
     }
+    Uint::from(10)
+        .checked_pow(Uint::from(exponent))
+        .and_then(|p| value.checked_mul(p))
+        .ok_or(NumberError::Overflow)
 }
 
-impl From<Vec<Token>> for TokenList {
-    fn from(tokens: Vec<Token>) -> Self {
+/// Parse digits in `radix`, with `_` allowed between them
+fn digits(digits: &str, radix: u32) -> Result<Uint, NumberError> {
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+    {
+        return Err(NumberError::Malformed);
+    }
+
+    let mut value = Uint::zero();
+    for c in digits.chars().filter(|&c| c != '_') {
+        // `to_digit` only takes ASCII digits
+        let digit = c.to_digit(radix).ok_or(NumberError::Malformed)?;
+        value = value
+            .checked_mul(Uint::from(radix))
+            .and_then(|v| v.checked_add(Uint::from(digit)))
+            .ok_or(NumberError::Overflow)?;
+    }
+    Ok(value)
+}
+
+impl From<Vec<Spanned<Token>>> for TokenList {
+    fn from(tokens: Vec<Spanned<Token>>) -> Self {
         TokenList(tokens)
     }
 }
@@ -115,14 +355,74 @@ pub mod tests {
     use super::*;
 
     // For unit testing in other modules
+    // Tokens are laid out one after the other, whitespaces being a single char
     pub fn token_list(v: Vec<Token>) -> TokenList {
-        TokenList(v)
+        let mut start = 0;
+        TokenList(
+            v.into_iter()
+                .map(|t| {
+                    let end = start + t.to_string().len().max(1);
+                    let span = Span::new(start, end);
+                    start = end;
+                    Spanned::new(t, span)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number("0"), Ok(Uint::zero()));
+        assert_eq!(number("1_000_000"), Ok(Uint::from(1_000_000)));
+        assert_eq!(number("0xff"), Ok(Uint::from(255)));
+        assert_eq!(number("0XDEAD_beef"), Ok(Uint::from(0xdead_beef_u64)));
+        assert_eq!(number("0b1010"), Ok(Uint::from(10)));
+        assert_eq!(number("0o777"), Ok(Uint::from(511)));
+        assert_eq!(number("007"), Ok(Uint::from(7)));
+        assert_eq!(number(&format!("0x{}", "f".repeat(64))), Ok(Uint::MAX));
+        assert_eq!(number(&Uint::MAX.to_string()), Ok(Uint::MAX));
+
+        for malformed in [
+            "0x", "0b2", "0o8", "0xg", "1e", "1_", "_1", "0x_1", "1__0", "1\u{663}",
+        ] {
+            assert_eq!(
+                number(malformed),
+                Err(NumberError::Malformed),
+                "{}",
+                malformed
+            );
+        }
+        assert_eq!(
+            number(&format!("0x1{}", "0".repeat(64))),
+            Err(NumberError::Overflow)
+        );
+        assert_eq!(
+            number(
+                "115792089237316195423570985008687907853269984665640564039457584007913129639936"
+            ),
+            Err(NumberError::Overflow)
+        );
+        assert_eq!(
+            number(&format!("0b1{}", "0".repeat(256))),
+            Err(NumberError::Overflow)
+        );
+
+        // Units only scale decimal numbers
+        for prefixed in ["0x10ether", "0x10 gwei", "0b1wei", "0o7 szabo"] {
+            assert_eq!(
+                number(prefixed),
+                Err(NumberError::UnitWithPrefix),
+                "{}",
+                prefixed
+            );
+        }
+        assert_eq!(number("0x10 wai"), Err(NumberError::Malformed));
     }
 
     #[test]
     fn test_lexer() {
         let source = "156 + 4 * 3 / 2";
-        let expected = TokenList(vec![
+        let expected = token_list(vec![
             Token::Num(156u8.into()),
             Token::Whitespace,
             Token::Add,
@@ -138,6 +438,172 @@ pub mod tests {
             Token::Num(2u8.into()),
         ]);
         assert_eq!(lexer(source), Ok(expected));
-        assert_eq!(lexer("1e"), Err(LexicalError::InvalidCharacter('e')));
+        assert_eq!(
+            lexer("1e"),
+            Err(LexicalError::InvalidNumber(
+                "1e".to_owned(),
+                NumberError::Malformed,
+                Span::new(0, 2)
+            ))
+        );
+        assert_eq!(
+            lexer("1\u{663}"),
+            Err(LexicalError::InvalidCharacter('\u{663}', Span::new(1, 3)))
+        );
+        assert_eq!(
+            lexer("1@"),
+            Err(LexicalError::InvalidCharacter('@', Span::new(1, 2)))
+        );
+
+        let source = "1.5 ether/20gwei+1e3 wei";
+        let tokens = lexer(source).unwrap();
+        assert_eq!(
+            tokens[..]
+                .iter()
+                .map(|t| (t.node.clone(), t.span))
+                .collect::<Vec<_>>(),
+            [
+                (Token::Num(Uint::exp10(17) * 15), Span::new(0, 9)),
+                (Token::Div, Span::new(9, 10)),
+                (Token::Num(Uint::exp10(10) * 2), Span::new(10, 16)),
+                (Token::Add, Span::new(16, 17)),
+                (Token::Num(Uint::from(1000)), Span::new(17, 24)),
+            ]
+        );
+        // Only units follow a number, names don't
+        assert_eq!(lexer("2 x").map(|t| t.len()), Ok(3));
+        assert_eq!(
+            lexer("1.5 wei"),
+            Err(LexicalError::InvalidNumber(
+                "1.5 wei".to_owned(),
+                NumberError::Fractional,
+                Span::new(0, 7)
+            ))
+        );
+        assert_eq!(
+            lexer("1."),
+            Err(LexicalError::InvalidCharacter('.', Span::new(1, 2)))
+        );
+
+        let source = "addmod(x_1, _y,2)";
+        let expected = token_list(vec![
+            Token::Ident("addmod".to_owned()),
+            Token::OpenParen,
+            Token::Ident("x_1".to_owned()),
+            Token::Comma,
+            Token::Whitespace,
+            Token::Ident("_y".to_owned()),
+            Token::Comma,
+            Token::Num(2u8.into()),
+            Token::CloseParen,
+        ]);
+        assert_eq!(lexer(source), Ok(expected));
+
+        let source = "let x=1;letx";
+        let expected = token_list(vec![
+            Token::Let,
+            Token::Whitespace,
+            Token::Ident("x".to_owned()),
+            Token::Assign,
+            Token::Num(1u8.into()),
+            Token::Semicolon,
+            Token::Ident("letx".to_owned()),
+        ]);
+        assert_eq!(lexer(source), Ok(expected));
+
+        let source = "$12+$";
+        assert_eq!(
+            lexer(source),
+            Err(LexicalError::InvalidCharacter('$', Span::new(4, 5)))
+        );
+        assert_eq!(
+            lexer("$12").map(|t| t[0].node.clone()),
+            Ok(Token::Ident("$12".to_owned()))
+        );
+
+        let source = "2**3%~4&5|6^7<<8>>9";
+        let expected = token_list(vec![
+            Token::Num(2u8.into()),
+            Token::Pow,
+            Token::Num(3u8.into()),
+            Token::Mod,
+            Token::BitNot,
+            Token::Num(4u8.into()),
+            Token::BitAnd,
+            Token::Num(5u8.into()),
+            Token::BitOr,
+            Token::Num(6u8.into()),
+            Token::BitXor,
+            Token::Num(7u8.into()),
+            Token::Shl,
+            Token::Num(8u8.into()),
+            Token::Shr,
+            Token::Num(9u8.into()),
+        ]);
+        assert_eq!(lexer(source), Ok(expected));
+
+        let source = "1<2>3<=4>=5==6!=7&&8||!9?a:b=c";
+        let expected = token_list(vec![
+            Token::Num(1u8.into()),
+            Token::Lt,
+            Token::Num(2u8.into()),
+            Token::Gt,
+            Token::Num(3u8.into()),
+            Token::Le,
+            Token::Num(4u8.into()),
+            Token::Ge,
+            Token::Num(5u8.into()),
+            Token::Eq,
+            Token::Num(6u8.into()),
+            Token::Ne,
+            Token::Num(7u8.into()),
+            Token::And,
+            Token::Num(8u8.into()),
+            Token::Or,
+            Token::Not,
+            Token::Num(9u8.into()),
+            Token::Question,
+            Token::Ident("a".to_owned()),
+            Token::Colon,
+            Token::Ident("b".to_owned()),
+            Token::Assign,
+            Token::Ident("c".to_owned()),
+        ]);
+        assert_eq!(lexer(source), Ok(expected));
+        let source = "if x then y else z";
+        assert_eq!(
+            lexer(source).map(|t| t[..]
+                .iter()
+                .map(|t| t.node.clone())
+                .filter(|t| *t != Token::Whitespace)
+                .collect::<Vec<_>>()),
+            Ok(vec![
+                Token::If,
+                Token::Ident("x".to_owned()),
+                Token::Then,
+                Token::Ident("y".to_owned()),
+                Token::Else,
+                Token::Ident("z".to_owned()),
+            ])
+        );
+        // Spans are in bytes
+        assert_eq!(
+            lexer("(é)"),
+            Err(LexicalError::InvalidCharacter('é', Span::new(1, 3)))
+        );
+        assert_eq!(
+            lexer("2 ** 10")
+                .unwrap()
+                .into_iter()
+                .map(|t| t.span)
+                .collect::<Vec<_>>(),
+            vec![
+                Span::new(0, 1),
+                Span::new(1, 2),
+                Span::new(2, 4),
+                Span::new(4, 5),
+                Span::new(5, 7)
+            ]
+        );
     }
 }
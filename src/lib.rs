@@ -1,23 +1,90 @@
+pub mod assembler;
+pub mod ast;
 pub mod codegen;
 pub mod lexer;
+pub mod optimizer;
 pub mod parser;
+pub mod pipeline;
+pub mod session;
 pub mod utils;
 
-pub use ethers_core::abi::Uint;
-use utils::errors::LexicalError;
+pub use codegen::{Bytecode, Contract, Mode};
+pub use ethers_core::{abi::Uint, types::I256};
+pub use pipeline::{Compilation, Pipeline};
+pub use session::Session;
+use utils::{errors::Error, evm::EVM, gas::DEFAULT_GAS_LIMIT, tracer::Tracer};
+pub use utils::{format::ResultFormat, gas::Fork};
 
-pub fn calculate(source: &str) -> Result<Uint, LexicalError> {
-    let tokens = lexer::lexer(source)?;
-    if cfg!(debug_assertions) {
-        println!("Lexer (str to Tokens)> {:?}", tokens);
-    }
-    let pn = parser::parse(tokens)?;
-    if cfg!(debug_assertions) {
-        println!("Parser (Tokens to Prefix Notation)> {}", pn);
-    }
-    let bytecode = pn.into();
-    if cfg!(debug_assertions) {
-        println!("Compiler (PN to Bytecode)> {}", bytecode);
+/// Calculation settings
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// How numbers are interpreted
+    pub mode: Mode,
+    /// Instruction set and gas schedule to compile and execute for
+    pub fork: Fork,
+    /// Gas available to the execution
+    pub gas_limit: u64,
+    /// Whether the bytecode goes through the optimizer
+    pub optimize: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            mode: Mode::default(),
+            fork: Fork::default(),
+            gas_limit: DEFAULT_GAS_LIMIT,
+            optimize: false,
+        }
     }
-    Ok(utils::evm::EVM::execute(&bytecode))
+}
+
+/// The result of a calculation and the gas its execution used
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Calculation<T = Uint> {
+    pub value: T,
+    pub gas_used: u64,
+}
+
+pub fn calculate(source: &str) -> Result<Calculation, Error> {
+    calculate_with(source, &Options::default())
+}
+
+/// Calculate in signed mode and interpret the result as an int256
+pub fn calculate_signed(source: &str) -> Result<Calculation<I256>, Error> {
+    let options = Options {
+        mode: Mode::Signed,
+        ..Options::default()
+    };
+    let calculation = calculate_with(source, &options)?;
+    Ok(Calculation {
+        value: I256::from_raw(calculation.value),
+        gas_used: calculation.gas_used,
+    })
+}
+
+pub fn calculate_with(source: &str, options: &Options) -> Result<Calculation, Error> {
+    trace_with(source, options, &mut ())
+}
+
+/// Calculate, reporting each step of the execution to `tracer`
+pub fn trace_with(
+    source: &str,
+    options: &Options,
+    tracer: &mut impl Tracer,
+) -> Result<Calculation, Error> {
+    let bytecode = compile_with(source, options)?;
+    let mut evm = EVM::new(options.fork, options.gas_limit);
+    let value = evm.execute_traced(&bytecode, tracer)?.value()?;
+    Ok(Calculation {
+        value,
+        gas_used: evm.gas.used,
+    })
+}
+
+/// Compile a calculation to bytecode without executing it
+pub fn compile_with(source: &str, options: &Options) -> Result<Bytecode, Error> {
+    Pipeline::new(*options)
+        .compile(source)
+        .map(|compilation| compilation.bytecode)
 }
@@ -1,20 +1,276 @@
-use std::io::Write;
+use std::{
+    io::{IsTerminal, Write},
+    process::ExitCode,
+};
 
-use mini_evm_calculator::calculate;
+use mini_evm_calculator::{
+    assembler::assemble,
+    lexer,
+    utils::{
+        diagnostics::{render, report, Origin},
+        evm::{disassemble, EVM},
+        tracer::Trace,
+    },
+    Bytecode, Fork, Mode, Options, ResultFormat, Session,
+};
 
-/// An exampfn main() -> Result<(), String> {
-fn main() -> Result<(), String> {
+const USAGE: &str = "Usage: mini_evm_calculator [OPTIONS] [FILE]
+
+Without an expression or a file, calculates the lines of stdin,
+or starts the interactive calculator when stdin is a terminal.
+
+Arguments:
+  [FILE]                 File with one calculation per line, - for stdin
+
+Options:
+  -e, --eval <CALC>      Calculate CALC, can be repeated
+      --emit <STAGE>     Stage to print: tokens, pn, ast, bytecode, asm, runtime, init
+                         or result [default: result]
+      --mode <MODE>      signed or unsigned [default: unsigned]
+      --format <FORMAT>  Result format: decimal, hex, binary, word, signed, ether or gwei [default: decimal]
+      --fork <FORK>      berlin, shanghai or cancun [default: cancun]
+      --gas <LIMIT>      Gas limit of each calculation [default: 30000000]
+      --no-optimize      Compile every operation as written, without the optimizer
+  -h, --help             Print this help";
+
+/// Stage of the pipeline printed for each calculation
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Emit {
+    Tokens,
+    /// The AST in Polish notation
+    Pn,
+    /// The AST in infix notation, with as few parentheses as possible
+    Ast,
+    Bytecode,
+    Asm,
+    /// Code of a contract returning the result
+    Runtime,
+    /// Code deploying that contract
+    Init,
+    Result,
+}
+
+impl std::str::FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "pn" => Ok(Emit::Pn),
+            "ast" => Ok(Emit::Ast),
+            "bytecode" => Ok(Emit::Bytecode),
+            "asm" => Ok(Emit::Asm),
+            "runtime" => Ok(Emit::Runtime),
+            "init" => Ok(Emit::Init),
+            "result" => Ok(Emit::Result),
+            _ => Err(format!("unknown stage: {}", s)),
+        }
+    }
+}
+
+/// Where the calculations come from
+enum Input {
+    Expressions(Vec<String>),
+    File(String),
+    Stdin,
+    Repl,
+}
+
+struct Args {
+    input: Input,
+    emit: Emit,
+    options: Options,
+    format: ResultFormat,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut session = Session::new(args.options);
+    session.format = args.format;
+    let res = match args.input {
+        Input::Repl => repl(&mut session),
+        Input::Expressions(expressions) => {
+            let lines = expressions.into_iter().map(Ok);
+            run(lines, "-e", &mut session, args.emit)
+        }
+        Input::Stdin => run(std::io::stdin().lines(), "stdin", &mut session, args.emit),
+        Input::File(path) => match std::fs::File::open(&path) {
+            Ok(file) => {
+                let lines = std::io::BufRead::lines(std::io::BufReader::new(file));
+                run(lines, &path, &mut session, args.emit)
+            }
+            Err(err) => Err(format!("{}: {}", path, err)),
+        },
+    };
+    match res {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parse the command line arguments, or return `None` when the help is asked for
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut expressions = Vec::new();
+    let mut file = None;
+    let mut emit = Emit::Result;
+    let mut options = Options {
+        optimize: true,
+        ..Options::default()
+    };
+    let mut format = ResultFormat::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} expects a value", name));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-e" | "--eval" => expressions.push(value(&arg)?),
+            "--emit" => emit = value(&arg)?.parse()?,
+            "--mode" => {
+                options.mode = match value(&arg)?.as_str() {
+                    "signed" => Mode::Signed,
+                    "unsigned" => Mode::Unsigned,
+                    mode => return Err(format!("unknown mode: {}", mode)),
+                }
+            }
+            "--fork" => options.fork = value(&arg)?.parse::<Fork>()?,
+            "--format" => format = value(&arg)?.parse()?,
+            "--no-optimize" => options.optimize = false,
+            "--gas" => {
+                let limit = value(&arg)?;
+                options.gas_limit = limit
+                    .parse()
+                    .map_err(|_| format!("invalid gas limit: {}", limit))?;
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option: {}", arg))
+            }
+            _ if file.is_some() => return Err(format!("unexpected argument: {}", arg)),
+            _ => file = Some(arg),
+        }
+    }
+
+    let input = match (expressions.is_empty(), file) {
+        (false, Some(_)) => return Err("expressions and a file can't be used together".to_owned()),
+        (false, None) => Input::Expressions(expressions),
+        (true, Some(file)) if file == "-" => Input::Stdin,
+        (true, Some(file)) => Input::File(file),
+        (true, None) if std::io::stdin().is_terminal() => Input::Repl,
+        (true, None) => Input::Stdin,
+    };
+    Ok(Some(Args {
+        input,
+        emit,
+        options,
+        format,
+    }))
+}
+
+/// Calculate every line and print the `emit` stage of each of them,
+/// returning whether they all succeeded
+fn run(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    input: &str,
+    session: &mut Session,
+    emit: Emit,
+) -> Result<bool, String> {
+    let mut success = true;
+    for (number, line) in lines.enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", input, e))?;
+        // Not trimmed, so that the columns of the errors are the ones in the input
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let origin = Origin {
+            input: Some(input),
+            line: number + 1,
+        };
+        match stage(line, origin, session, emit) {
+            Ok(output) => writeln!(std::io::stdout(), "{}", output).map_err(|e| e.to_string())?,
+            Err(err) => {
+                eprintln!("{}", err);
+                success = false;
+                continue;
+            }
+        }
+        // Assignments are calculated whatever the stage, to keep them for the next lines
+        if emit != Emit::Result && session.is_assignment(line) {
+            if let Err(err) = session.eval(line) {
+                eprintln!("{}", report(line, origin, &err));
+                success = false;
+            }
+        }
+    }
+    Ok(success)
+}
+
+/// Return the `emit` stage of a line, calculating it only for the result,
+/// `origin` being where the line is for the errors
+fn stage(line: &str, origin: Origin, session: &mut Session, emit: Emit) -> Result<String, String> {
+    let report = |e| report(line, origin, &e);
+    let output = match emit {
+        Emit::Tokens => lexer::lexer(line)
+            .map_err(|e| report(e.into()))?
+            .to_string(),
+        Emit::Pn => session.parse(line).map_err(report)?.to_sexp(),
+        Emit::Ast => session.parse(line).map_err(report)?.to_string(),
+        Emit::Bytecode => format!("{:#x}", session.compile(line).map_err(report)?),
+        Emit::Asm => disassemble(&session.compile(line).map_err(report)?)
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Emit::Runtime => format!("{:#x}", session.contract(line).map_err(report)?.runtime),
+        Emit::Init => format!("{:#x}", session.contract(line).map_err(report)?.init),
+        Emit::Result => {
+            let res = session.eval(line).map_err(report)?;
+            session.format(res.value)
+        }
+    };
+    Ok(output)
+}
+
+/// The interactive calculator
+fn repl(session: &mut Session) -> Result<bool, String> {
     println!("Execute a calculation on a rough mini EVM calculator 😀");
-    println!("⚠ You can only use + * - / ( ) and numeric characters. Enter \"exit\" to exit\n");
+    println!("⚠ You can only use + - * / % ** & | ^ ~ << >>, the comparisons below, ( ) and numbers like 1_000, 0xff, 0b101 or 0o17. Enter \"exit\" to exit");
+    println!("💰 Scale numbers with wei, gwei, szabo, finney or ether, e.g. \"1.5 ether / 3\", or with e.g. 1e18");
+    println!("📝 Name values with \"let x = 3 * 4; x + 1\"");
+    println!("🔀 Compare with < > <= >= == != && || ! and branch with \"if gas > 21000 then 2 else 1\" or \"gas > 21000 ? 2 : 1\"");
+    println!("💾 Keep a result with \"fee = 21000 * 3\", reuse the last one with \"ans\" or \"_\" and earlier ones with \"$1\", \"$2\"...");
+    println!("⚙ Enter \":mode signed\" or \":mode unsigned\" to switch between int256 and uint256");
+    println!("🖨 Enter \":format decimal|hex|binary|word|signed|ether|gwei\" to change how results are written");
+    println!(
+        "⛽ Enter \":fork berlin|shanghai|cancun\" to pick the gas schedule and \":gas <limit>\" to set the gas limit"
+    );
+    println!("🚀 Enter \":optimize off\" to compile every operation as written, \":optimize on\" to fold constants again");
+    println!("🔧 Enter \":disasm <bytecode or calculation>\" to list its instructions and \":asm <mnemonics>\" to run assembly, e.g. \":asm PUSH1 2 PUSH1 3 ADD\"");
+    println!(
+        "🔍 Enter \":trace <calculation>\" to print its execution trace as EIP-3155 JSON lines\n"
+    );
 
-    loop {
-        let line = readline()?;
+    // Stops at the end of the input too
+    while let Some(line) = readline()? {
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
 
-        match respond(line) {
+        match respond(line, session) {
             Ok(quit) => {
                 if quit {
                     break;
@@ -27,31 +283,165 @@ fn main() -> Result<(), String> {
         }
     }
 
-    Ok(())
+    Ok(true)
 }
 
-fn respond(line: &str) -> Result<bool, String> {
+fn respond(line: &str, session: &mut Session) -> Result<bool, String> {
     match line {
         "exit" => {
             write!(std::io::stdout(), "Exiting ...").map_err(|e| e.to_string())?;
             std::io::stdout().flush().map_err(|e| e.to_string())?;
             Ok(true)
         }
+        ":mode signed" | ":mode unsigned" => {
+            session.options.mode = if line.ends_with("unsigned") {
+                Mode::Unsigned
+            } else {
+                Mode::Signed
+            };
+            write!(std::io::stdout(), "mode> {:?}", session.options.mode)
+                .map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        _ if line.starts_with(":format ") => {
+            session.format = line[":format ".len()..].trim().parse::<ResultFormat>()?;
+            write!(std::io::stdout(), "format> {}", session.format).map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        ":optimize on" | ":optimize off" => {
+            session.options.optimize = line.ends_with("on");
+            write!(
+                std::io::stdout(),
+                "optimize> {}",
+                if session.options.optimize {
+                    "on"
+                } else {
+                    "off"
+                }
+            )
+            .map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        _ if line.starts_with(":fork ") => {
+            session.options.fork = line[":fork ".len()..].trim().parse::<Fork>()?;
+            write!(std::io::stdout(), "fork> {}", session.options.fork)
+                .map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        _ if line.starts_with(":gas ") => {
+            let limit = line[":gas ".len()..].trim();
+            session.options.gas_limit = limit
+                .parse()
+                .map_err(|_| format!("invalid gas limit: {}", limit))?;
+            write!(
+                std::io::stdout(),
+                "gas limit> {}",
+                session.options.gas_limit
+            )
+            .map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        _ if line.starts_with(":disasm ") => {
+            let source = line[":disasm ".len()..].trim();
+            // Bytecode when it is a bare hex literal, a calculation like `0xff + 1` otherwise
+            let hex = source
+                .strip_prefix("0x")
+                .or_else(|| source.strip_prefix("0X"))
+                .is_some_and(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()));
+            let (bytecode, optimization) = match hex {
+                true => {
+                    let bytecode = source
+                        .parse::<Bytecode>()
+                        .map_err(|e| format!("error: {}", e))?;
+                    (bytecode, None)
+                }
+                false => {
+                    let bytecode = session
+                        .compile(source)
+                        .map_err(|e| report(source, Origin::default(), &e))?;
+                    let optimization = match session.options.optimize {
+                        true => Some(
+                            session
+                                .report(source)
+                                .map_err(|e| report(source, Origin::default(), &e))?,
+                        ),
+                        false => None,
+                    };
+                    (bytecode, optimization)
+                }
+            };
+            for instruction in disassemble(&bytecode) {
+                writeln!(std::io::stdout(), "{}", instruction).map_err(|e| e.to_string())?;
+            }
+            if let Some(optimization) = optimization {
+                writeln!(std::io::stdout(), "; optimized {}", optimization)
+                    .map_err(|e| e.to_string())?;
+            }
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        _ if line.starts_with(":asm ") => {
+            let source = &line[":asm ".len()..];
+            let bytecode =
+                assemble(source).map_err(|e| render(source, Origin::default(), e.span(), &e))?;
+            let mut evm = EVM::new(session.options.fork, session.options.gas_limit);
+            let res = evm
+                .execute(&bytecode)
+                .and_then(|outcome| outcome.value())
+                .map_err(|e| format!("error: {}", e))?;
+            writeln!(
+                std::io::stdout(),
+                "result> {:#x} = {} (gas: {})",
+                bytecode,
+                res,
+                evm.gas.used
+            )
+            .map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+        _ if line.starts_with(":trace ") => {
+            let source = &line[":trace ".len()..];
+            let mut trace = Trace::default();
+            let res = session.eval_traced(source, &mut trace);
+            // The trace is empty when the calculation doesn't compile
+            if !trace.steps.is_empty() || trace.error.is_some() {
+                writeln!(std::io::stdout(), "{}", trace.to_eip3155()).map_err(|e| e.to_string())?;
+            }
+            res.map_err(|e| report(source, Origin::default(), &e))?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+            Ok(false)
+        }
         _ => {
-            let res = calculate(line).map_err(|e| e.to_string())?;
-            write!(std::io::stdout(), "result> {} = {}\n", line, res).map_err(|e| e.to_string())?;
+            let res = session
+                .eval(line)
+                .map_err(|e| report(line, Origin::default(), &e))?;
+            writeln!(
+                std::io::stdout(),
+                "result> {} = {} (gas: {})",
+                line,
+                session.format(res.value),
+                res.gas_used
+            )
+            .map_err(|e| e.to_string())?;
             std::io::stdout().flush().map_err(|e| e.to_string())?;
             Ok(false)
         }
     }
 }
 
-fn readline() -> Result<String, String> {
+/// Read a line, or `None` at the end of the input
+fn readline() -> Result<Option<String>, String> {
     write!(std::io::stdout(), "\nEVM calculator> ").map_err(|e| e.to_string())?;
     std::io::stdout().flush().map_err(|e| e.to_string())?;
     let mut buffer = String::new();
-    std::io::stdin()
+    let read = std::io::stdin()
         .read_line(&mut buffer)
         .map_err(|e| e.to_string())?;
-    Ok(buffer)
+    Ok((read > 0).then_some(buffer))
 }
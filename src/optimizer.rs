@@ -0,0 +1,567 @@
+use std::{collections::HashMap, fmt::Display};
+
+use ethers_core::abi::Uint;
+
+use crate::{
+    ast::{BinaryOp, Expr, ExprKind},
+    codegen::{self, Bytecode},
+    utils::{
+        evm::{disassemble, Instruction, Opcode, EVM},
+        span::Span,
+    },
+    Options,
+};
+
+/// Rewrite an expression into one with the same value and cheaper bytecode
+///
+/// - Constant subexpressions are calculated on the EVM, unless their value takes more bytes
+///   to push than the code calculating it, e.g. `-1` stays `0 - 1`
+/// - Identities are removed, e.g. `x + 0` and `x * 1`
+/// - Multiplications by a power of two become shifts, e.g. `x * 8` is `x << 3`
+/// - Conditionals on a constant are replaced with the branch they take
+pub fn optimize(expr: &Expr, options: &Options) -> Expr {
+    let kind = match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => return expr.clone(),
+        ExprKind::Unary { op, expr } => ExprKind::Unary {
+            op: *op,
+            expr: Box::new(optimize(expr, options)),
+        },
+        ExprKind::Binary { .. } => {
+            let (first, operations) = expr.chain();
+            let first = optimize(first, options);
+            return operations
+                .into_iter()
+                .fold(first, |lhs, (operation, op, rhs)| {
+                    let kind = ExprKind::Binary {
+                        op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(optimize(rhs, options)),
+                    };
+                    reduce(Expr::new(kind, operation.span), options)
+                });
+        }
+        ExprKind::Call { name, args } => ExprKind::Call {
+            name: name.clone(),
+            args: args.iter().map(|arg| optimize(arg, options)).collect(),
+        },
+        ExprKind::Let { name, value, body } => ExprKind::Let {
+            name: name.clone(),
+            value: Box::new(optimize(value, options)),
+            body: Box::new(optimize(body, options)),
+        },
+        ExprKind::If {
+            condition,
+            then,
+            otherwise,
+        } => ExprKind::If {
+            condition: Box::new(optimize(condition, options)),
+            then: Box::new(optimize(then, options)),
+            otherwise: Box::new(optimize(otherwise, options)),
+        },
+    };
+    reduce(Expr::new(kind, expr.span), options)
+}
+
+/// Replace the names that aren't bound in the expression with their value in `constants`,
+/// so that the optimizer calculates with them too
+pub fn substitute(expr: &Expr, constants: &HashMap<String, Uint>) -> Expr {
+    substitute_unbound(expr, constants, &mut Vec::new())
+}
+
+/// Replace the names with their value in `constants`, unless they are `bound` by a let
+fn substitute_unbound<'a>(
+    expr: &'a Expr,
+    constants: &HashMap<String, Uint>,
+    bound: &mut Vec<&'a str>,
+) -> Expr {
+    let kind = match &expr.kind {
+        ExprKind::Variable(name) if !bound.contains(&name.as_str()) => match constants.get(name) {
+            Some(value) => ExprKind::Literal(*value),
+            None => return expr.clone(),
+        },
+        ExprKind::Literal(_) | ExprKind::Variable(_) => return expr.clone(),
+        ExprKind::Unary { op, expr } => ExprKind::Unary {
+            op: *op,
+            expr: Box::new(substitute_unbound(expr, constants, bound)),
+        },
+        ExprKind::Binary { .. } => {
+            let (first, operations) = expr.chain();
+            let first = substitute_unbound(first, constants, bound);
+            return operations
+                .into_iter()
+                .fold(first, |lhs, (operation, op, rhs)| {
+                    let kind = ExprKind::Binary {
+                        op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(substitute_unbound(rhs, constants, bound)),
+                    };
+                    Expr::new(kind, operation.span)
+                });
+        }
+        ExprKind::Call { name, args } => ExprKind::Call {
+            name: name.clone(),
+            args: args
+                .iter()
+                .map(|arg| substitute_unbound(arg, constants, bound))
+                .collect(),
+        },
+        ExprKind::Let { name, value, body } => {
+            let value = substitute_unbound(value, constants, bound);
+            bound.push(name);
+            let body = substitute_unbound(body, constants, bound);
+            bound.pop();
+            ExprKind::Let {
+                name: name.clone(),
+                value: Box::new(value),
+                body: Box::new(body),
+            }
+        }
+        ExprKind::If {
+            condition,
+            then,
+            otherwise,
+        } => ExprKind::If {
+            condition: Box::new(substitute_unbound(condition, constants, bound)),
+            then: Box::new(substitute_unbound(then, constants, bound)),
+            otherwise: Box::new(substitute_unbound(otherwise, constants, bound)),
+        },
+    };
+    Expr::new(kind, expr.span)
+}
+
+/// Calculate an expression whose operands are optimized if it is constant,
+/// simplify it otherwise
+fn reduce(expr: Expr, options: &Options) -> Expr {
+    match fold(&expr, options) {
+        Some(literal) => literal,
+        None => simplify(expr),
+    }
+}
+
+/// The literal a constant expression calculates, if it is cheaper than the expression
+fn fold(expr: &Expr, options: &Options) -> Option<Expr> {
+    if !is_constant(expr) {
+        return None;
+    }
+    let options = Options {
+        optimize: false,
+        ..*options
+    };
+    // Expressions that don't compile are left for the codegen to report
+    let code = codegen::compile(expr, &options).ok()?;
+    let value = EVM::new(options.fork, u64::MAX)
+        .execute(&code)
+        .and_then(|outcome| outcome.value())
+        .ok()?;
+    let literal = Expr::new(ExprKind::Literal(value), expr.span);
+    let literal_size = codegen::compile(&literal, &options).ok()?.as_ref().len();
+    (literal_size <= code.as_ref().len()).then_some(literal)
+}
+
+/// Whether an expression doesn't depend on any name
+fn is_constant(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) => true,
+        ExprKind::Variable(_) | ExprKind::Let { .. } => false,
+        ExprKind::Unary { expr, .. } => is_constant(expr),
+        ExprKind::Binary { .. } => {
+            let (first, operations) = expr.chain();
+            is_constant(first) && operations.iter().all(|(_, _, rhs)| is_constant(rhs))
+        }
+        ExprKind::Call { args, .. } => args.iter().all(is_constant),
+        ExprKind::If {
+            condition,
+            then,
+            otherwise,
+        } => is_constant(condition) && is_constant(then) && is_constant(otherwise),
+    }
+}
+
+/// Remove the identities of an operation, turn multiplications by a power of two into shifts
+/// and pick the branch of a conditional on a literal
+fn simplify(expr: Expr) -> Expr {
+    let span = expr.span;
+    let (op, lhs, rhs) = match expr.kind {
+        ExprKind::Binary { op, lhs, rhs } => (op, lhs, rhs),
+        ExprKind::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            return match condition.kind {
+                ExprKind::Literal(u) if u.is_zero() => otherwise.with_span(span),
+                ExprKind::Literal(_) => then.with_span(span),
+                _ => Expr::new(
+                    ExprKind::If {
+                        condition,
+                        then,
+                        otherwise,
+                    },
+                    span,
+                ),
+            }
+        }
+        _ => return expr,
+    };
+    let literal = |e: &Expr| match e.kind {
+        ExprKind::Literal(u) => Some(u),
+        _ => None,
+    };
+    let (left, right) = (literal(&lhs), literal(&rhs));
+    let zero = Some(Uint::zero());
+    let one = Some(Uint::one());
+    match op {
+        BinaryOp::Add | BinaryOp::BitOr | BinaryOp::BitXor if left == zero => rhs.with_span(span),
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::BitOr
+        | BinaryOp::BitXor
+        | BinaryOp::Shl
+        | BinaryOp::Shr
+            if right == zero =>
+        {
+            lhs.with_span(span)
+        }
+        BinaryOp::Mul if left == one => rhs.with_span(span),
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Pow if right == one => lhs.with_span(span),
+        BinaryOp::Mul => match (right.and_then(log2), left.and_then(log2)) {
+            (Some(amount), _) => shift(*lhs, amount, span),
+            (_, Some(amount)) => shift(*rhs, amount, span),
+            _ => Expr::new(ExprKind::Binary { op, lhs, rhs }, span),
+        },
+        _ => Expr::new(ExprKind::Binary { op, lhs, rhs }, span),
+    }
+}
+
+/// The exponent of a power of two
+fn log2(u: Uint) -> Option<usize> {
+    (!u.is_zero() && (u & (u - 1)).is_zero()).then(|| u.bits() - 1)
+}
+
+/// `expr << amount`
+fn shift(expr: Expr, amount: usize, span: Span) -> Expr {
+    let amount = Expr::new(ExprKind::Literal(Uint::from(amount)), span);
+    Expr::binary(BinaryOp::Shl, expr, amount).with_span(span)
+}
+
+/// Remove instruction sequences that don't change the stack
+///
+/// - `DUPn POP` and `PUSHn x POP`
+/// - `DUP1 SWAP1 POP`, left by `let x = 1; x`
+/// - `SWAP1 SWAP1`
+/// - `SWAP1` before a commutative operation, e.g. `SWAP1 ADD`
+///
+/// DUPs and SWAPs are only removed when the stack has the values they use,
+/// so that code running out of values still fails.
+///
+/// Bytecode with invalid or truncated instructions is left as is, and so is bytecode
+/// with jumps or PC since removing instructions would move the offsets they use.
+pub fn peephole(bytecode: &Bytecode) -> Bytecode {
+    let mut instructions = Vec::new();
+    for instruction in disassemble(bytecode) {
+        match instruction {
+            Instruction::Op {
+                opcode: Opcode::JUMP | Opcode::JUMPI | Opcode::JUMPDEST | Opcode::PC,
+                ..
+            } => return bytecode.clone(),
+            Instruction::Op {
+                opcode, immediate, ..
+            } => instructions.push((opcode, immediate)),
+            _ => return bytecode.clone(),
+        }
+    }
+
+    // The instructions with the stack height before them, 0 past an instruction that fails
+    let mut optimized: Vec<(Opcode, Vec<u8>, usize)> = Vec::new();
+    for (opcode, immediate) in instructions {
+        let height = optimized.last().map_or(0, |&(op, _, height)| {
+            let (inputs, outputs) = op.stack_effect();
+            height.checked_sub(inputs).map_or(0, |h| h + outputs)
+        });
+        optimized.push((opcode, immediate, height));
+        // Each instruction can complete a pattern that the ones before it start
+        loop {
+            let opcodes = optimized.iter().map(|(op, ..)| *op).collect::<Vec<_>>();
+            // Stack height before the `n`th instruction from the end
+            let height = |n: usize| optimized[optimized.len() - n].2;
+            let removed = match opcodes.as_slice() {
+                [.., Opcode::DUP1, Opcode::SWAP1, Opcode::POP] if height(3) >= 1 => 3,
+                [.., Opcode::SWAP1, Opcode::SWAP1] if height(2) >= 2 => 2,
+                [.., op, Opcode::POP] if pushes_only(*op, height(2)) => 2,
+                [.., Opcode::SWAP1, Opcode::ADD | Opcode::MUL | Opcode::AND | Opcode::OR | Opcode::XOR] =>
+                {
+                    // Safe unwraps here
+                    let (op, immediate, _) = optimized.pop().unwrap();
+                    let (_, _, height) = optimized.pop().unwrap();
+                    optimized.push((op, immediate, height));
+                    continue;
+                }
+                _ => break,
+            };
+            optimized.truncate(optimized.len() - removed);
+        }
+    }
+
+    let code = optimized
+        .into_iter()
+        .flat_map(|(opcode, immediate, _)| std::iter::once(opcode as u8).chain(immediate))
+        .collect::<Vec<_>>();
+    Bytecode::from(code)
+}
+
+/// Whether an instruction only pushes a value, without reading any,
+/// on a stack `height` values high
+fn pushes_only(op: Opcode, height: usize) -> bool {
+    op == Opcode::PUSH0
+        || op.immediate_size() > 0
+        || (1..=height.min(16)).any(|n| Opcode::dup(n) == Some(op))
+}
+
+/// Bytecode size and gas before and after optimizing
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Report {
+    pub size_before: usize,
+    pub size_after: usize,
+    /// Gas the execution uses, `None` if it fails
+    pub gas_before: Option<u64>,
+    pub gas_after: Option<u64>,
+}
+
+impl Report {
+    /// Compare the bytecode before and after optimizing,
+    /// executing both of them to measure their gas
+    pub fn new(before: &Bytecode, after: &Bytecode, options: &Options) -> Self {
+        let gas = |code: &Bytecode| {
+            let mut evm = EVM::new(options.fork, options.gas_limit);
+            evm.execute(code)
+                .and_then(|outcome| outcome.value())
+                .ok()
+                .map(|_| evm.gas.used)
+        };
+        Report {
+            size_before: before.as_ref().len(),
+            size_after: after.as_ref().len(),
+            gas_before: gas(before),
+            gas_after: gas(after),
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gas = |gas: Option<u64>| gas.map_or("failed".to_owned(), |g| g.to_string());
+        write!(
+            f,
+            "size: {} -> {} bytes, gas: {} -> {}",
+            self.size_before,
+            self.size_after,
+            gas(self.gas_before),
+            gas(self.gas_after)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::{
+            tests::{call, if_, let_, num, var},
+            UnaryOp,
+        },
+        codegen::tests::bytecode,
+        utils::gas::Fork,
+        Mode,
+    };
+
+    fn sexp(expr: Expr) -> String {
+        optimize(&expr, &Options::default()).to_sexp()
+    }
+
+    #[test]
+    fn test_optimize() {
+        // Constant folding
+        let e = Expr::binary(
+            BinaryOp::Add,
+            num(156),
+            Expr::binary(
+                BinaryOp::Div,
+                Expr::binary(BinaryOp::Mul, num(4), num(3)),
+                num(2),
+            ),
+        );
+        assert_eq!(sexp(e), "162");
+        assert_eq!(sexp(call("addmod", vec![num(5), num(6), num(7)])), "4");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Div, num(1), num(0))), "0");
+        // EVM semantics, in the mode of the options
+        let e = Expr::binary(
+            BinaryOp::Div,
+            Expr::unary(UnaryOp::Neg, num(8)),
+            Expr::unary(UnaryOp::Neg, num(2)),
+        );
+        let signed = Options {
+            mode: Mode::Signed,
+            ..Options::default()
+        };
+        assert_eq!(optimize(&e, &signed).kind, ExprKind::Literal(Uint::from(4)));
+        // Unless the value is larger than the code
+        assert_eq!(sexp(Expr::unary(UnaryOp::Neg, num(1))), "(neg 1)");
+        assert_eq!(
+            sexp(Expr::binary(BinaryOp::Pow, num(2), num(255))),
+            "(exp 2 255)"
+        );
+        // Errors are left for the codegen
+        assert_eq!(sexp(call("sqrt", vec![num(4)])), "(sqrt 4)");
+        // Names aren't constant
+        assert_eq!(
+            sexp(let_(
+                "x",
+                Expr::binary(BinaryOp::Add, num(1), num(2)),
+                var("x")
+            )),
+            "(let x 3 x)"
+        );
+
+        // Identities
+        let x = || var("x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Add, x(), num(0))), "x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Add, num(0), x())), "x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Sub, x(), num(0))), "x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Sub, num(0), x())), "(sub 0 x)");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Mul, num(1), x())), "x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Div, x(), num(1))), "x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Div, num(1), x())), "(div 1 x)");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Pow, x(), num(1))), "x");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Shr, x(), num(0))), "x");
+        assert_eq!(
+            sexp(Expr::binary(
+                BinaryOp::Add,
+                x(),
+                Expr::binary(BinaryOp::Sub, num(2), num(2))
+            )),
+            "x"
+        );
+
+        // Shifts
+        assert_eq!(sexp(Expr::binary(BinaryOp::Mul, x(), num(8))), "(shl x 3)");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Mul, num(2), x())), "(shl x 1)");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Mul, x(), num(6))), "(mul x 6)");
+        assert_eq!(sexp(Expr::binary(BinaryOp::Mul, x(), num(0))), "(mul x 0)");
+
+        // Conditions
+        let e = Expr::binary(BinaryOp::Ge, num(3), num(2));
+        assert_eq!(sexp(e), "1");
+        let e = if_(Expr::binary(BinaryOp::Lt, num(3), num(2)), num(1), num(2));
+        assert_eq!(sexp(e), "2");
+        let e = if_(num(7), x(), Expr::binary(BinaryOp::Add, x(), num(1)));
+        assert_eq!(sexp(e), "x");
+        let e = if_(num(0), x(), Expr::binary(BinaryOp::Mul, x(), num(2)));
+        assert_eq!(sexp(e), "(shl x 1)");
+        let e = if_(x(), Expr::binary(BinaryOp::Add, num(1), num(2)), num(4));
+        assert_eq!(sexp(e), "(if x 3 4)");
+    }
+
+    #[test]
+    fn test_substitute() {
+        let constants =
+            HashMap::from([("x".to_owned(), Uint::from(2)), ("y".to_owned(), 3.into())]);
+        // let x = x + 1; x * y + z
+        let e = let_(
+            "x",
+            Expr::binary(BinaryOp::Add, var("x"), num(1)),
+            Expr::binary(
+                BinaryOp::Add,
+                Expr::binary(BinaryOp::Mul, var("x"), var("y")),
+                var("z"),
+            ),
+        );
+        let substituted = substitute(&e, &constants);
+        assert_eq!(substituted.to_sexp(), "(let x (add 2 1) (add (mul x 3) z))");
+        assert_eq!(
+            optimize(&substituted, &Options::default()).to_sexp(),
+            "(let x 3 (add (mul x 3) z))"
+        );
+    }
+
+    #[test]
+    fn test_peephole() {
+        let optimized = |s: &str| peephole(&bytecode(s.to_owned())).to_string();
+        // PUSH1 1 DUP1 SWAP1 POP
+        assert_eq!(optimized("6001809050"), "6001");
+        // PUSH1 1 PUSH1 2 SWAP1 SWAP1 ADD
+        assert_eq!(optimized("60016002909001"), "6001600201");
+        // PUSH1 1 PUSH1 2 SWAP1 MUL, PUSH1 1 PUSH1 2 SWAP1 SUB
+        assert_eq!(optimized("600160029002"), "6001600202");
+        assert_eq!(optimized("600160029003"), "600160029003");
+        // PUSH1 1 PUSH1 2 POP DUP1 POP, nested patterns
+        assert_eq!(optimized("60016002508050"), "6001");
+        // Invalid code is left as is
+        assert_eq!(optimized("6001EE5050"), "6001EE5050");
+        // And so is code with jumps, PUSH1 1 PUSH1 7 JUMP DUP1 POP JUMPDEST
+        assert_eq!(optimized("600160075680505B"), "600160075680505B");
+        // DUPs and SWAPs without enough values fail, DUP1 POP, DUP1 SWAP1 POP, PUSH1 1 SWAP1 SWAP1
+        assert_eq!(optimized("8050"), "8050");
+        assert_eq!(optimized("809050"), "809050");
+        assert_eq!(optimized("600190906001"), "600190906001");
+        // PUSH1 1 DUP2 POP, PUSH1 1 PUSH1 2 DUP2 POP
+        assert_eq!(optimized("60018150"), "60018150");
+        assert_eq!(optimized("600160028150"), "60016002");
+        let mut evm = EVM::new(Fork::default(), u64::MAX);
+        assert!(evm.execute(&bytecode(optimized("8050"))).is_err());
+    }
+
+    #[test]
+    fn test_compile_optimized() {
+        let options = Options {
+            optimize: true,
+            ..Options::default()
+        };
+        // let x = 2 * 3; x * 4
+        let e = let_(
+            "x",
+            Expr::binary(BinaryOp::Mul, num(2), num(3)),
+            Expr::binary(BinaryOp::Mul, var("x"), num(4)),
+        );
+        let before = codegen::compile(&e, &Options::default()).unwrap();
+        let after = codegen::compile(&e, &options).unwrap();
+        // PUSH1 6 DUP1 PUSH1 2 SHL SWAP1 POP
+        assert_eq!(after, bytecode("60068060021B9050".to_owned()));
+        let report = Report::new(&before, &after, &options);
+        assert_eq!(
+            report,
+            Report {
+                size_before: 11,
+                size_after: 8,
+                gas_before: Some(27),
+                gas_after: Some(17),
+            }
+        );
+        assert_eq!(report.to_string(), "size: 11 -> 8 bytes, gas: 27 -> 17");
+
+        // Folded literals are pushed for the fork
+        let berlin = Options {
+            fork: Fork::Berlin,
+            ..options
+        };
+        let e = Expr::binary(BinaryOp::Sub, num(1), num(1));
+        assert_eq!(
+            codegen::compile(&e, &berlin),
+            Ok(bytecode("6000".to_owned()))
+        );
+        assert_eq!(
+            codegen::compile(&e, &options),
+            Ok(bytecode("5F".to_owned()))
+        );
+
+        let report = Report::new(
+            &before,
+            &after,
+            &Options {
+                gas_limit: 20,
+                ..options
+            },
+        );
+        assert_eq!((report.gas_before, report.gas_after), (None, Some(17)));
+        assert_eq!(report.to_string(), "size: 11 -> 8 bytes, gas: failed -> 17");
+    }
+}
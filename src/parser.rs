@@ -1,119 +1,300 @@
-use std::{collections::VecDeque, fmt::Display};
+use std::{iter::Peekable, vec::IntoIter};
 
 use crate::{
+    ast::{BinaryOp, Expr, ExprKind, UnaryOp},
     lexer::TokenList,
-    utils::{errors::LexicalError, tokens::Token},
+    utils::{
+        errors::LexicalError,
+        span::{Span, Spanned},
+        tokens::{Associativity, Token},
+    },
 };
 
-/// Polish notation token list
-#[derive(PartialEq, Eq, Debug)]
-pub struct PNTokenList(VecDeque<Token>);
-
-pub fn parse(tokens: TokenList) -> Result<PNTokenList, LexicalError> {
-    let len = tokens.len();
-    // For operators
-    let mut stack = Vec::with_capacity(len);
-
-    tokens
-        .clone()
-        .into_iter()
-        // Start from the ending token
-        .rev()
-        // Loop over all the tokens and yield output in a queue
-        .try_fold(VecDeque::with_capacity(len), |mut acc, t| match t {
-            // Operands
-            Token::Num(_) => {
-                acc.push_back(t);
-                Ok(acc)
+/// How deep expressions can nest in parentheses, prefix operations, `**`, let bindings
+/// and conditionals, past it the parser, the optimizer and the codegen would run out of stack
+pub const MAX_DEPTH: usize = 128;
+
+/// How many operations can follow each other, each one an operand of the next,
+/// e.g. `1 + 1 + … + 1`
+///
+/// The passes over the tree walk such chains with a loop, but dropping the tree doesn't.
+pub const MAX_CHAIN: usize = 4096;
+
+/// The parser encapsulated in a struct.
+struct Parser {
+    /// The remaining tokens, without whitespaces.
+    tokens: Peekable<IntoIter<Spanned<Token>>>,
+    /// The empty span after the last token, where the input ends.
+    end: Span,
+    /// Number of expressions being parsed, each one inside the previous one.
+    depth: usize,
+}
+
+/// Build the abstract syntax tree of a token list
+pub fn parse(tokens: TokenList) -> Result<Expr, LexicalError> {
+    let end = tokens[..].last().map_or(0, |t| t.span.end);
+    let mut parser = Parser {
+        tokens: tokens
+            .into_iter()
+            .filter(|t| t.node != Token::Whitespace)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .peekable(),
+        end: Span::new(end, end),
+        depth: 0,
+    };
+    let expr = parser.block(None)?;
+    match parser.tokens.next() {
+        None => Ok(expr),
+        Some(Spanned {
+            node: Token::CloseParen,
+            span,
+        }) => Err(LexicalError::MismatchedParenthesis(span)),
+        Some(t) => Err(LexicalError::UnexpectedToken(t.node, t.span)),
+    }
+}
+
+impl Parser {
+    /// Parse `let name = value;` bindings followed by the expression they are in scope of,
+    /// `after` being the operator the expression is an operand of
+    fn block(&mut self, after: Option<Spanned<Token>>) -> Result<Expr, LexicalError> {
+        let Some(start) = self.next_if(Token::Let) else {
+            return self.expression(0, after);
+        };
+        let depth = self.depth;
+        self.nest()?;
+        let Some(Spanned {
+            node: Token::Ident(name),
+            ..
+        }) = self.tokens.next_if(|t| matches!(t.node, Token::Ident(_)))
+        else {
+            return Err(self.expected("a name after 'let'"));
+        };
+        let Some(assign) = self.next_if(Token::Assign) else {
+            return Err(self.expected(&format!("'=' after 'let {}'", name)));
+        };
+        let value = self.expression(0, Some(Spanned::new(Token::Assign, assign)))?;
+        let Some(semicolon) = self.next_if(Token::Semicolon) else {
+            return Err(self.expected(&format!("';' after the value of {}", name)));
+        };
+        let body = self.block(Some(Spanned::new(Token::Semicolon, semicolon)))?;
+        self.depth = depth;
+        let span = start.to(body.span);
+        Ok(Expr::new(
+            ExprKind::Let {
+                name,
+                value: Box::new(value),
+                body: Box::new(body),
+            },
+            span,
+        ))
+    }
+
+    /// Parse an expression whose infix operators have at least the given precedence
+    /// (precedence climbing), `after` being the operator the expression is an operand of
+    ///
+    /// At the lowest precedence the expression can be the condition of `c ? a : b`,
+    /// which binds looser than any operator and nests to the right.
+    fn expression(
+        &mut self,
+        min_precedence: u8,
+        after: Option<Spanned<Token>>,
+    ) -> Result<Expr, LexicalError> {
+        let depth = self.depth;
+        self.nest()?;
+        let mut lhs = self.prefix(after)?;
+        // Chains like `1 + 1 + 1` nest to the left without nesting the calls,
+        // so they don't count towards the depth
+        let mut lhs_depth = lhs.depth();
+        while let Some(o) = self
+            .tokens
+            .peek()
+            .and_then(|t| t.node.operator())
+            .filter(|o| o.arity == 2 && o.precedence >= min_precedence)
+        {
+            // Safe unwraps here
+            let t = self.tokens.next().unwrap();
+            let op = BinaryOp::from_token(&t.node).unwrap();
+            let span = t.span;
+            let rhs = match o.associativity {
+                Associativity::Left => self.expression(o.precedence + 1, Some(t))?,
+                Associativity::Right => self.expression(o.precedence, Some(t))?,
+            };
+            lhs_depth = lhs_depth.max(rhs.depth()) + 1;
+            if lhs_depth > MAX_CHAIN + 1 {
+                return Err(LexicalError::TooLong(span));
             }
-            _ if t.operator().is_some() => {
-                // Safe unwrap here
-                let o = t.operator().unwrap();
-                while stack
-                    .last()
-                    .and_then(|t2: &Token| t2.operator())
-                    .filter(|o2| o.has_lower_precedence_than(o2))
-                    .is_some()
-                {
-                    // Safe unwrap here
-                    acc.push_back(stack.pop().unwrap());
+            lhs = Expr::binary(op, lhs, rhs);
+        }
+        self.depth = depth;
+        if min_precedence > 0 {
+            return Ok(lhs);
+        }
+        match self.next_if(Token::Question) {
+            Some(question) => self.ternary(lhs, question),
+            None => Ok(lhs),
+        }
+    }
+
+    /// Parse the branches of `condition ? a : b`, after the `?` at `question`
+    fn ternary(&mut self, condition: Expr, question: Span) -> Result<Expr, LexicalError> {
+        let then = self.expression(0, Some(Spanned::new(Token::Question, question)))?;
+        let Some(colon) = self.next_if(Token::Colon) else {
+            return Err(self.expected("':' after the first branch of '?'"));
+        };
+        let otherwise = self.expression(0, Some(Spanned::new(Token::Colon, colon)))?;
+        let span = condition.span.to(otherwise.span);
+        Ok(Expr::new(
+            ExprKind::If {
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            },
+            span,
+        ))
+    }
+
+    /// Parse an operand: a number, a name, a call, a conditional,
+    /// a parenthesized or prefixed expression
+    fn prefix(&mut self, after: Option<Spanned<Token>>) -> Result<Expr, LexicalError> {
+        let Some(Spanned { node, span }) = self.tokens.next_if(|t| starts_operand(&t.node)) else {
+            return Err(self.missing_operand(after));
+        };
+        match node {
+            Token::Num(n) => Ok(Expr::new(ExprKind::Literal(n), span)),
+            Token::Ident(name) => {
+                if let Some(open) = self.next_if(Token::OpenParen) {
+                    let (args, close) = self.arguments(open)?;
+                    Ok(Expr::new(ExprKind::Call { name, args }, span.to(close)))
+                } else {
+                    Ok(Expr::new(ExprKind::Variable(name), span))
                 }
-                stack.push(t);
-                Ok(acc)
-            }
-            Token::CloseParen => {
-                stack.push(t);
-                Ok(acc)
             }
             Token::OpenParen => {
-                while stack.last().filter(|&l| l != &Token::CloseParen).is_some() {
-                    // Safe unwrap here
-                    acc.push_back(stack.pop().unwrap());
+                if let Some(close) = self.next_if(Token::CloseParen) {
+                    return Err(LexicalError::EmptyParentheses(span.to(close)));
                 }
-
-                if stack.is_empty() {
-                    return Err(LexicalError::MismatchedParenthesis);
-                }
-                // pop off '('
-                // Safe unwrap here
-                stack.pop().unwrap();
-                Ok(acc)
+                let expr = self.block(None)?;
+                let close = self.close(span)?;
+                Ok(expr.with_span(span.to(close)))
             }
-            _ => Ok(acc),
-        })
-        // empty the operator stack
-        .and_then(move |mut v| {
-            while stack.last().is_some() {
-                // Safe unwrap here
-                v.push_back(stack.pop().unwrap());
+            Token::If => self.conditional(span),
+            t if UnaryOp::from_token(&t).is_some() => {
+                // Safe unwraps here
+                let op = UnaryOp::from_token(&t).unwrap();
+                let o = op.token().operator().unwrap();
+                let expr = self.expression(o.precedence, Some(Spanned::new(t, span)))?;
+                let span = span.to(expr.span);
+                Ok(Expr::unary(op, expr).with_span(span))
             }
+            // Filtered out by `starts_operand`
+            _ => unreachable!(),
+        }
+    }
+
+    /// Parse `if c then a else b`, after the `if` at `start`
+    ///
+    /// The else branch extends as far as possible, like the body of a let binding.
+    fn conditional(&mut self, start: Span) -> Result<Expr, LexicalError> {
+        let condition = self.expression(0, Some(Spanned::new(Token::If, start)))?;
+        let Some(then_span) = self.next_if(Token::Then) else {
+            return Err(self.expected("'then' after the condition"));
+        };
+        let then = self.expression(0, Some(Spanned::new(Token::Then, then_span)))?;
+        let Some(else_span) = self.next_if(Token::Else) else {
+            return Err(self.expected("'else' after the 'then' branch"));
+        };
+        let otherwise = self.expression(0, Some(Spanned::new(Token::Else, else_span)))?;
+        let span = start.to(otherwise.span);
+        Ok(Expr::new(
+            ExprKind::If {
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            },
+            span,
+        ))
+    }
+
+    /// Enter an expression inside the ones being parsed, failing past [`MAX_DEPTH`]
+    fn nest(&mut self) -> Result<(), LexicalError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            let span = self.tokens.peek().map_or(self.end, |t| t.span);
+            return Err(LexicalError::TooDeep(span));
+        }
+        Ok(())
+    }
 
-            if stack.is_empty() {
-                Ok(v.into_iter().rev().collect::<VecDeque<_>>())
-            } else {
-                Err(LexicalError::InvalidTokenList(tokens))
+    /// Error for an operand that isn't there, `after` being the operator expecting it
+    fn missing_operand(&mut self, after: Option<Spanned<Token>>) -> LexicalError {
+        match (after, self.tokens.next()) {
+            // e.g. `3 +`
+            (Some(op), _) => LexicalError::MissingOperand(op.node, op.span),
+            // e.g. `+ 3`
+            (None, Some(t)) if t.node.operator().filter(|o| o.arity == 2).is_some() => {
+                LexicalError::MissingLeftOperand(t.node, t.span)
             }
-        })
-        .map(|q| q.into())
-}
+            (None, Some(t)) => LexicalError::UnexpectedToken(t.node, t.span),
+            (None, None) => LexicalError::UnexpectedEnd(self.end),
+        }
+    }
 
-impl From<VecDeque<Token>> for PNTokenList {
-    fn from(v: VecDeque<Token>) -> Self {
-        PNTokenList(v)
+    /// Error for a token that isn't the `expected` one, pointing at the next token
+    fn expected(&mut self, expected: &str) -> LexicalError {
+        let span = self.tokens.peek().map_or(self.end, |t| t.span);
+        LexicalError::Expected(expected.to_owned(), span)
     }
-}
 
-impl IntoIterator for PNTokenList {
-    type Item = Token;
-    type IntoIter = std::collections::vec_deque::IntoIter<Self::Item>;
+    /// Parse the comma separated arguments of a call, up to the closing parenthesis,
+    /// and return them with the span of the closing parenthesis
+    fn arguments(&mut self, open: Span) -> Result<(Vec<Expr>, Span), LexicalError> {
+        let mut args = Vec::new();
+        if let Some(close) = self.next_if(Token::CloseParen) {
+            return Ok((args, close));
+        }
+        loop {
+            args.push(self.expression(0, None)?);
+            if self.next_if(Token::Comma).is_none() {
+                let close = self.close(open)?;
+                return Ok((args, close));
+            }
+        }
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+    /// Consume the parenthesis closing the one at `open` and return its span
+    fn close(&mut self, open: Span) -> Result<Span, LexicalError> {
+        match self.tokens.next() {
+            Some(Spanned {
+                node: Token::CloseParen,
+                span,
+            }) => Ok(span),
+            None => Err(LexicalError::MismatchedParenthesis(open)),
+            Some(t) => Err(LexicalError::UnexpectedToken(t.node, t.span)),
+        }
     }
-}
 
-impl Display for PNTokenList {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.0
-                .iter()
-                .map(|t| t.to_string())
-                .collect::<Vec<String>>()
-                .join(" ")
-        )
+    /// Consume the next token if it is `token` and return its span
+    fn next_if(&mut self, token: Token) -> Option<Span> {
+        self.tokens.next_if(|t| t.node == token).map(|t| t.span)
     }
 }
 
+/// Whether a token can be the first one of an operand
+fn starts_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Num(_) | Token::Ident(_) | Token::OpenParen | Token::If
+    ) || UnaryOp::from_token(token).is_some()
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::lexer;
+    use crate::{ast::tests::num, lexer};
 
-    // For unit testing in other modules
-    pub fn pn_token_list(v: VecDeque<Token>) -> PNTokenList {
-        PNTokenList(v)
+    fn sexp(source: &str) -> String {
+        parse(lexer::lexer(source).unwrap()).unwrap().to_sexp()
     }
 
     #[test]
@@ -134,18 +315,17 @@ pub mod tests {
             Token::Whitespace,
             Token::Num(2u8.into()),
         ]);
-        // + 156 / * 4 3 2
-        let expected = vec![
-            Token::Add,
-            Token::Num(156u8.into()),
-            Token::Div,
-            Token::Mul,
-            Token::Num(4u8.into()),
-            Token::Num(3u8.into()),
-            Token::Num(2u8.into()),
-        ]
-        .into();
-        assert_eq!(parse(v), Ok(PNTokenList(expected)));
+        // (add 156 (div (mul 4 3) 2))
+        let expected = Expr::binary(
+            BinaryOp::Add,
+            num(156),
+            Expr::binary(
+                BinaryOp::Div,
+                Expr::binary(BinaryOp::Mul, num(4), num(3)),
+                num(2),
+            ),
+        );
+        assert_eq!(parse(v).map(|e| e.to_sexp()), Ok(expected.to_sexp()));
 
         // (156 + 4) * 3 / 2
         let v = lexer::tests::token_list(vec![
@@ -165,17 +345,356 @@ pub mod tests {
             Token::Whitespace,
             Token::Num(2u8.into()),
         ]);
-        // / * + 156 4 3 2
-        let expected = vec![
-            Token::Div,
-            Token::Mul,
-            Token::Add,
-            Token::Num(156u8.into()),
-            Token::Num(4u8.into()),
-            Token::Num(3u8.into()),
-            Token::Num(2u8.into()),
-        ]
-        .into();
-        assert_eq!(parse(v), Ok(PNTokenList(expected)));
+        // (div (mul (add 156 4) 3) 2)
+        let expected = Expr::binary(
+            BinaryOp::Div,
+            Expr::binary(
+                BinaryOp::Mul,
+                Expr::binary(BinaryOp::Add, num(156), num(4)),
+                num(3),
+            ),
+            num(2),
+        );
+        let ast = parse(v).unwrap();
+        assert_eq!(ast.to_sexp(), expected.to_sexp());
+        assert_eq!(ast.span, Span::new(0, 17));
+        match ast.kind {
+            ExprKind::Binary { lhs, .. } => match lhs.kind {
+                // The parentheses are part of the span
+                ExprKind::Binary { lhs, .. } => assert_eq!(lhs.span, Span::new(0, 9)),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parser_negation() {
+        assert_eq!(sexp("-3 + 5"), "(add (neg 3) 5)");
+        assert_eq!(sexp("4 * (-2)"), "(mul 4 (neg 2))");
+        assert_eq!(sexp("1 - -2 * 3"), "(sub 1 (mul (neg 2) 3))");
+        assert_eq!(sexp("--2"), "(neg (neg 2))");
+    }
+
+    #[test]
+    fn test_parser_precedence() {
+        // Exponentiation is right associative
+        assert_eq!(sexp("2 ** 3 ** 2"), "(exp 2 (exp 3 2))");
+        // and binds tighter than negation
+        assert_eq!(sexp("-2 ** 2"), "(neg (exp 2 2))");
+        assert_eq!(sexp("2 ** -1"), "(exp 2 (neg 1))");
+        assert_eq!(sexp("~1 * 2"), "(mul (not 1) 2)");
+        assert_eq!(sexp("1 + 2 % 3"), "(add 1 (mod 2 3))");
+        assert_eq!(sexp("1 << 2 + 3"), "(shl 1 (add 2 3))");
+        assert_eq!(
+            sexp("1 | 2 ^ 3 & 4 >> 5"),
+            "(or 1 (xor 2 (and 3 (shr 4 5))))"
+        );
+        assert_eq!(sexp("8 >> 1 >> 1"), "(shr (shr 8 1) 1)");
+    }
+
+    #[test]
+    fn test_parser_calls() {
+        assert_eq!(sexp("addmod(1, 2 * 3, n)"), "(addmod 1 (mul 2 3) n)");
+        assert_eq!(sexp("f()"), "(f)");
+        assert_eq!(sexp("-f(g(x)) + y"), "(add (neg (f (g x))) y)");
+
+        let parse = |s: &str| parse(lexer::lexer(s).unwrap());
+        assert_eq!(
+            parse("(1 + 2"),
+            Err(LexicalError::MismatchedParenthesis(Span::new(0, 1)))
+        );
+        assert_eq!(
+            parse("1 + 2)"),
+            Err(LexicalError::MismatchedParenthesis(Span::new(5, 6)))
+        );
+        assert_eq!(
+            parse("f(1, 2"),
+            Err(LexicalError::MismatchedParenthesis(Span::new(1, 2)))
+        );
+        assert_eq!(
+            parse("f(1,)"),
+            Err(LexicalError::UnexpectedToken(
+                Token::CloseParen,
+                Span::new(4, 5)
+            ))
+        );
+        assert_eq!(
+            parse("1 +"),
+            Err(LexicalError::MissingOperand(Token::Add, Span::new(2, 3)))
+        );
+        assert_eq!(parse("-f(2) * 3").unwrap().span, Span::new(0, 9));
+    }
+
+    #[test]
+    fn test_parser_let() {
+        assert_eq!(sexp("let x = 3 * 4; x + 1"), "(let x (mul 3 4) (add x 1))");
+        assert_eq!(
+            sexp("let a = 1; let b = a; (let c = b; c) * a"),
+            "(let a 1 (let b a (mul (let c b c) a)))"
+        );
+
+        let parse = |s: &str| parse(lexer::lexer(s).unwrap());
+        assert_eq!(parse(" let x = 1; x").unwrap().span, Span::new(1, 13));
+        assert_eq!(
+            parse("let 1 = 2; 3"),
+            Err(LexicalError::Expected(
+                "a name after 'let'".to_owned(),
+                Span::new(4, 5)
+            ))
+        );
+        assert_eq!(
+            parse("let x 2; x"),
+            Err(LexicalError::Expected(
+                "'=' after 'let x'".to_owned(),
+                Span::new(6, 7)
+            ))
+        );
+        assert_eq!(
+            parse("let x = 2 x"),
+            Err(LexicalError::Expected(
+                "';' after the value of x".to_owned(),
+                Span::new(10, 11)
+            ))
+        );
+        assert_eq!(
+            parse("let x = 2"),
+            Err(LexicalError::Expected(
+                "';' after the value of x".to_owned(),
+                Span::new(9, 9)
+            ))
+        );
+        assert_eq!(
+            parse("let x = ; x"),
+            Err(LexicalError::MissingOperand(Token::Assign, Span::new(6, 7)))
+        );
+        assert_eq!(
+            parse("let x = 1;"),
+            Err(LexicalError::MissingOperand(
+                Token::Semicolon,
+                Span::new(9, 10)
+            ))
+        );
+        assert_eq!(
+            parse("1 + let x = 1; x"),
+            Err(LexicalError::MissingOperand(Token::Add, Span::new(2, 3)))
+        );
+        assert_eq!(
+            parse("1; 2"),
+            Err(LexicalError::UnexpectedToken(
+                Token::Semicolon,
+                Span::new(1, 2)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parser_conditions() {
+        assert_eq!(sexp("1 + 2 < 3 << 1"), "(lt (add 1 2) (shl 3 1))");
+        assert_eq!(sexp("x & 1 == 1"), "(eq (and x 1) 1)");
+        assert_eq!(
+            sexp("a < b || a >= c && !d != 0"),
+            "(lor (lt a b) (land (ge a c) (ne (lnot d) 0)))"
+        );
+        assert_eq!(sexp("a <= b == (c > d)"), "(eq (le a b) (gt c d))");
+
+        assert_eq!(sexp("x > 1 ? x * 2 : x"), "(if (gt x 1) (mul x 2) x)");
+        // The ternary nests to the right and binds looser than `||`
+        assert_eq!(
+            sexp("a || b ? 1 : c ? 2 : 3"),
+            "(if (lor a b) 1 (if c 2 3))"
+        );
+        assert_eq!(sexp("a ? b ? 1 : 2 : 3"), "(if a (if b 1 2) 3)");
+        assert_eq!(sexp("let x = a ? 1 : 2; x"), "(let x (if a 1 2) x)");
+        assert_eq!(sexp("f(a ? 1 : 2, 3)"), "(f (if a 1 2) 3)");
+
+        assert_eq!(
+            sexp("if x > 1 then x * 2 else x + 1"),
+            "(if (gt x 1) (mul x 2) (add x 1))"
+        );
+        assert_eq!(
+            sexp("2 * if a then 1 else if b then 2 else 3"),
+            "(mul 2 (if a 1 (if b 2 3)))"
+        );
+        assert_eq!(sexp("(if a then 1 else 2) + 3"), "(add (if a 1 2) 3)");
+
+        let parse = |s: &str| parse(lexer::lexer(s).unwrap());
+        assert_eq!(parse("if a then 1 else 2").unwrap().span, Span::new(0, 18));
+        assert_eq!(parse("a ? 1 : 2").unwrap().span, Span::new(0, 9));
+        assert_eq!(
+            parse("if a 1 else 2"),
+            Err(LexicalError::Expected(
+                "'then' after the condition".to_owned(),
+                Span::new(5, 6)
+            ))
+        );
+        assert_eq!(
+            parse("if a then 1"),
+            Err(LexicalError::Expected(
+                "'else' after the 'then' branch".to_owned(),
+                Span::new(11, 11)
+            ))
+        );
+        assert_eq!(
+            parse("if then 1 else 2"),
+            Err(LexicalError::MissingOperand(Token::If, Span::new(0, 2)))
+        );
+        assert_eq!(
+            parse("a ? 1"),
+            Err(LexicalError::Expected(
+                "':' after the first branch of '?'".to_owned(),
+                Span::new(5, 5)
+            ))
+        );
+        assert_eq!(
+            parse("a ? 1 :"),
+            Err(LexicalError::MissingOperand(Token::Colon, Span::new(6, 7)))
+        );
+        assert_eq!(
+            parse("1 <"),
+            Err(LexicalError::MissingOperand(Token::Lt, Span::new(2, 3)))
+        );
+        assert_eq!(
+            parse("1 ? 2 : 3 : 4"),
+            Err(LexicalError::UnexpectedToken(
+                Token::Colon,
+                Span::new(10, 11)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parser_depth() {
+        let parse = |s: &str| parse(lexer::lexer(s).unwrap());
+        let nested = |n: usize| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(
+            parse(&nested(MAX_DEPTH)),
+            Err(LexicalError::TooDeep(Span::new(128, 129)))
+        );
+        assert_eq!(
+            parse(&nested(100_000)),
+            Err(LexicalError::TooDeep(Span::new(128, 129)))
+        );
+        assert!(matches!(
+            parse(&"-".repeat(100_000)),
+            Err(LexicalError::TooDeep(_))
+        ));
+        assert!(matches!(
+            parse(&"let x = 1; ".repeat(100_000)),
+            Err(LexicalError::TooDeep(_))
+        ));
+
+        // Chains don't nest, but they can't be longer than MAX_CHAIN operations
+        let chain = |n: usize| vec!["1"; n].join(" + ");
+        assert!(parse(&chain(MAX_DEPTH + 1)).is_ok());
+        assert!(parse(&format!(
+            "{}{}{}",
+            "(".repeat(100),
+            chain(1000),
+            ")".repeat(100)
+        ))
+        .is_ok());
+        let ast = parse(&chain(MAX_CHAIN + 1)).unwrap();
+        assert_eq!(ast.depth(), MAX_CHAIN + 1);
+        assert_eq!(ast.span, Span::new(0, 4 * MAX_CHAIN + 1));
+        assert_eq!(
+            parse(&chain(MAX_CHAIN + 2)),
+            Err(LexicalError::TooLong(Span::new(16386, 16387)))
+        );
+        assert!(matches!(
+            parse(&format!("({}) * 2", chain(MAX_CHAIN + 1))),
+            Err(LexicalError::TooLong(_))
+        ));
+        assert!(matches!(
+            parse(&format!("2 * ({})", chain(MAX_CHAIN + 1))),
+            Err(LexicalError::TooLong(_))
+        ));
+        // `**` nests to the right
+        assert!(parse(&vec!["1"; MAX_DEPTH].join(" ** ")).is_ok());
+        assert!(matches!(
+            parse(&vec!["1"; MAX_DEPTH + 1].join(" ** ")),
+            Err(LexicalError::TooDeep(_))
+        ));
+        assert_eq!(parse("-(1 + 2) * 3").unwrap().depth(), 4);
+    }
+
+    #[test]
+    fn test_parser_malformed() {
+        let parse = |s: &str| parse(lexer::lexer(s).unwrap());
+        assert_eq!(
+            parse("1 2"),
+            Err(LexicalError::UnexpectedToken(
+                Token::Num(2u8.into()),
+                Span::new(2, 3)
+            ))
+        );
+        assert_eq!(
+            parse("+"),
+            Err(LexicalError::MissingLeftOperand(
+                Token::Add,
+                Span::new(0, 1)
+            ))
+        );
+        assert_eq!(
+            parse("+ 3"),
+            Err(LexicalError::MissingLeftOperand(
+                Token::Add,
+                Span::new(0, 1)
+            ))
+        );
+        assert_eq!(
+            parse("3 +"),
+            Err(LexicalError::MissingOperand(Token::Add, Span::new(2, 3)))
+        );
+        assert_eq!(
+            parse("3 + * 4"),
+            Err(LexicalError::MissingOperand(Token::Add, Span::new(2, 3)))
+        );
+        assert_eq!(
+            parse("(3 -)"),
+            Err(LexicalError::MissingOperand(Token::Sub, Span::new(3, 4)))
+        );
+        assert_eq!(
+            parse("2 * -"),
+            Err(LexicalError::MissingOperand(Token::Sub, Span::new(4, 5)))
+        );
+        assert_eq!(
+            parse("()"),
+            Err(LexicalError::EmptyParentheses(Span::new(0, 2)))
+        );
+        assert_eq!(
+            parse("1 + ( )"),
+            Err(LexicalError::EmptyParentheses(Span::new(4, 7)))
+        );
+        assert_eq!(
+            parse("(1 2)"),
+            Err(LexicalError::UnexpectedToken(
+                Token::Num(2u8.into()),
+                Span::new(3, 4)
+            ))
+        );
+        assert_eq!(
+            parse("x y"),
+            Err(LexicalError::UnexpectedToken(
+                Token::Ident("y".to_owned()),
+                Span::new(2, 3)
+            ))
+        );
+        assert_eq!(
+            parse(","),
+            Err(LexicalError::UnexpectedToken(Token::Comma, Span::new(0, 1)))
+        );
+        assert_eq!(parse(""), Err(LexicalError::UnexpectedEnd(Span::new(0, 0))));
+
+        assert_eq!(parse("1 2").unwrap_err().to_string(), "Unexpected number");
+        assert_eq!(
+            parse("3 +").unwrap_err().to_string(),
+            "Missing operand after '+'"
+        );
+        assert_eq!(
+            parse("+ 3").unwrap_err().to_string(),
+            "Missing operand before '+'"
+        );
     }
 }
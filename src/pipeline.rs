@@ -0,0 +1,272 @@
+use std::io::Write;
+
+use crate::{
+    ast::Expr,
+    codegen::{self, Bytecode},
+    lexer::{self, TokenList},
+    optimizer::Report,
+    parser,
+    utils::{errors::Error, evm::EVM, tracer::Trace},
+    Calculation, Options,
+};
+
+/// Hook into the stages of a pipeline, called with each artifact once it is produced
+pub trait Observer {
+    fn on_tokens(&mut self, _tokens: &TokenList) {}
+
+    fn on_ast(&mut self, _ast: &Expr) {}
+
+    fn on_bytecode(&mut self, _bytecode: &Bytecode) {}
+
+    fn on_result(&mut self, _calculation: &Calculation) {}
+}
+
+/// The observer that doesn't observe
+impl Observer for () {}
+
+/// Observer logging each stage to a writer, one line per stage
+///
+/// ```text
+/// Lexer (str to Tokens)> [...]
+/// Parser (Tokens to AST)> (add 1 2)
+/// Compiler (AST to Bytecode)> 6002600101
+/// EVM (Bytecode to result)> 3 (gas: 9)
+/// ```
+#[derive(Debug)]
+pub struct Log<W: Write>(pub W);
+
+impl<W: Write> Observer for Log<W> {
+    // Logging is best effort, a failing writer doesn't fail the calculation
+    fn on_tokens(&mut self, tokens: &TokenList) {
+        let _ = writeln!(self.0, "Lexer (str to Tokens)> {:?}", tokens);
+    }
+
+    fn on_ast(&mut self, ast: &Expr) {
+        let _ = writeln!(self.0, "Parser (Tokens to AST)> {}", ast.to_sexp());
+    }
+
+    fn on_bytecode(&mut self, bytecode: &Bytecode) {
+        let _ = writeln!(self.0, "Compiler (AST to Bytecode)> {}", bytecode);
+    }
+
+    fn on_result(&mut self, calculation: &Calculation) {
+        let _ = writeln!(
+            self.0,
+            "EVM (Bytecode to result)> {} (gas: {})",
+            calculation.value, calculation.gas_used
+        );
+    }
+}
+
+/// Every artifact of a calculation
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Compilation {
+    pub tokens: TokenList,
+    pub ast: Expr,
+    pub bytecode: Bytecode,
+    /// What the optimizer saved, when the options turn it on
+    pub optimization: Option<Report>,
+    /// Steps of the execution, empty until executed or when the pipeline doesn't trace
+    pub trace: Trace,
+    /// The result, once executed
+    pub result: Option<Calculation>,
+}
+
+impl Compilation {
+    /// The AST in Polish notation
+    pub fn pn(&self) -> String {
+        self.ast.to_sexp()
+    }
+}
+
+/// The stages of a calculation: source to tokens, to AST, to bytecode, to result
+///
+/// ```
+/// use mini_evm_calculator::{Options, Pipeline, Uint};
+///
+/// let compilation = Pipeline::new(Options::default()).run("1 + 2").unwrap();
+/// assert_eq!(compilation.pn(), "(add 1 2)");
+/// assert_eq!(compilation.result.map(|c| c.value), Some(Uint::from(3)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pipeline<O = ()> {
+    pub options: Options,
+    /// Whether the execution records a trace
+    pub trace: bool,
+    observer: O,
+}
+
+impl Pipeline {
+    pub fn new(options: Options) -> Self {
+        Pipeline {
+            options,
+            trace: false,
+            observer: (),
+        }
+    }
+}
+
+impl<O: Observer> Pipeline<O> {
+    /// Report the stages of the pipeline to `observer`
+    pub fn with_observer<P: Observer>(self, observer: P) -> Pipeline<P> {
+        Pipeline {
+            options: self.options,
+            trace: self.trace,
+            observer,
+        }
+    }
+
+    /// Give the observer back
+    pub fn into_observer(self) -> O {
+        self.observer
+    }
+
+    /// Compile a calculation without executing it
+    pub fn compile(&mut self, source: &str) -> Result<Compilation, Error> {
+        let tokens = lexer::lexer(source)?;
+        self.observer.on_tokens(&tokens);
+        let ast = parser::parse(tokens.clone())?;
+        self.observer.on_ast(&ast);
+        let bytecode = codegen::compile(&ast, &self.options)?;
+        self.observer.on_bytecode(&bytecode);
+        let optimization = match self.options.optimize {
+            true => {
+                let options = Options {
+                    optimize: false,
+                    ..self.options
+                };
+                let before = codegen::compile(&ast, &options)?;
+                Some(Report::new(&before, &bytecode, &self.options))
+            }
+            false => None,
+        };
+        Ok(Compilation {
+            tokens,
+            ast,
+            bytecode,
+            optimization,
+            trace: Trace::default(),
+            result: None,
+        })
+    }
+
+    /// Execute a compiled calculation, keeping its trace and result in it
+    pub fn execute(&mut self, compilation: &mut Compilation) -> Result<Calculation, Error> {
+        let mut evm = EVM::new(self.options.fork, self.options.gas_limit);
+        let mut trace = Trace::default();
+        let value = if self.trace {
+            let outcome = evm.execute_traced(&compilation.bytecode, &mut trace);
+            compilation.trace = trace;
+            outcome?.value()?
+        } else {
+            evm.execute(&compilation.bytecode)?.value()?
+        };
+        let calculation = Calculation {
+            value,
+            gas_used: evm.gas.used,
+        };
+        self.observer.on_result(&calculation);
+        compilation.result = Some(calculation);
+        Ok(calculation)
+    }
+
+    /// Compile and execute a calculation
+    pub fn run(&mut self, source: &str) -> Result<Compilation, Error> {
+        let mut compilation = self.compile(source)?;
+        self.execute(&mut compilation)?;
+        Ok(compilation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::tests::bytecode,
+        utils::{errors::ExecutionError, gas::Fork},
+        Uint,
+    };
+
+    #[test]
+    fn test_pipeline() {
+        let mut pipeline = Pipeline::new(Options::default());
+        let compilation = pipeline.run("1 + 2").unwrap();
+        assert_eq!(compilation.tokens.len(), 5);
+        assert_eq!(compilation.pn(), "(add 1 2)");
+        assert_eq!(compilation.bytecode, bytecode("6002600101".to_owned()));
+        assert!(compilation.trace.steps.is_empty());
+        assert_eq!(compilation.optimization, None);
+        assert_eq!(
+            compilation.result,
+            Some(Calculation {
+                value: Uint::from(3),
+                gas_used: 9
+            })
+        );
+
+        // Compile only, then execute with a trace
+        pipeline.trace = true;
+        let mut compilation = pipeline.compile("2 * 3").unwrap();
+        assert_eq!(compilation.result, None);
+        assert_eq!(
+            pipeline.execute(&mut compilation).map(|c| c.value),
+            Ok(Uint::from(6))
+        );
+        assert_eq!(compilation.trace.steps.len(), 3);
+
+        // The trace is kept when the execution fails
+        pipeline.options = Options {
+            fork: Fork::Berlin,
+            gas_limit: 5,
+            ..Options::default()
+        };
+        let mut compilation = pipeline.compile("2 * 3").unwrap();
+        assert_eq!(
+            pipeline.execute(&mut compilation),
+            Err(Error::Execution(ExecutionError::OutOfGas {
+                offset: 2,
+                gas_limit: 5
+            }))
+        );
+        assert_eq!(compilation.trace.steps.len(), 2);
+        assert_eq!(compilation.result, None);
+
+        // The optimizer reports what it saved
+        pipeline.options.optimize = true;
+        let compilation = pipeline.compile("2 * 3").unwrap();
+        assert_eq!(compilation.bytecode, bytecode("6006".to_owned()));
+        assert_eq!(
+            compilation.optimization.map(|r| r.to_string()),
+            Some("size: 5 -> 2 bytes, gas: failed -> 3".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_log() {
+        let mut pipeline = Pipeline::new(Options::default()).with_observer(Log(Vec::new()));
+        pipeline.run("1 + 2").unwrap();
+        let log = String::from_utf8(pipeline.into_observer().0).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Lexer (str to Tokens)> "));
+        assert_eq!(
+            lines[1..],
+            [
+                "Parser (Tokens to AST)> (add 1 2)",
+                "Compiler (AST to Bytecode)> 6002600101",
+                "EVM (Bytecode to result)> 3 (gas: 9)",
+            ]
+        );
+
+        // Stages after an error aren't logged
+        let mut pipeline = Pipeline::new(Options::default()).with_observer(Log(Vec::new()));
+        assert!(pipeline.run("1 +").is_err());
+        assert_eq!(
+            String::from_utf8(pipeline.into_observer().0)
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use ethers_core::abi::Uint;
+
+use crate::{
+    ast::Expr,
+    codegen::{self, Bytecode, Contract},
+    lexer::{self, TokenList},
+    optimizer::Report,
+    parser,
+    utils::{
+        errors::{CompileError, Error},
+        evm::EVM,
+        span::Spanned,
+        tokens::Token,
+        tracer::Tracer,
+    },
+    Calculation, Mode, Options, ResultFormat,
+};
+
+/// Names of the last result
+const ANS: [&str; 2] = ["ans", "_"];
+
+/// Calculations sharing their results
+///
+/// - `ans` and `_` are the last result
+/// - `$1`, `$2`... are the results in the order they were calculated
+/// - `name = calculation` keeps the result as `name` for the next calculations
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub options: Options,
+    /// How results are written out
+    pub format: ResultFormat,
+    /// Results of the successful calculations, `$1` being the first one
+    pub history: Vec<Uint>,
+    /// Values assigned to a name
+    pub variables: HashMap<String, Uint>,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        Session {
+            options,
+            ..Session::default()
+        }
+    }
+
+    /// The last result
+    pub fn ans(&self) -> Option<Uint> {
+        self.history.last().copied()
+    }
+
+    /// Write `value` in the session format,
+    /// decimal results being int256 in signed mode
+    pub fn format(&self, value: Uint) -> String {
+        match (self.format, self.options.mode) {
+            (ResultFormat::Decimal, Mode::Signed) => ResultFormat::Signed.format(value),
+            (format, _) => format.format(value),
+        }
+    }
+
+    /// Calculate a line and remember its result
+    pub fn eval(&mut self, line: &str) -> Result<Calculation, Error> {
+        self.eval_traced(line, &mut ())
+    }
+
+    /// Calculate a line and remember its result, reporting each step of the execution to `tracer`
+    pub fn eval_traced(
+        &mut self,
+        line: &str,
+        tracer: &mut impl Tracer,
+    ) -> Result<Calculation, Error> {
+        let (name, bytecode) = self.compile_line(line)?;
+        let mut evm = EVM::new(self.options.fork, self.options.gas_limit);
+        let value = evm.execute_traced(&bytecode, tracer)?.value()?;
+        if let Some(name) = name {
+            self.variables.insert(name, value);
+        }
+        self.history.push(value);
+        Ok(Calculation {
+            value,
+            gas_used: evm.gas.used,
+        })
+    }
+
+    /// Compile a line with the values of the session, without calculating it
+    pub fn compile(&self, line: &str) -> Result<Bytecode, Error> {
+        self.compile_line(line).map(|(_, bytecode)| bytecode)
+    }
+
+    /// Compile a line to a contract returning its result, without calculating it
+    pub fn contract(&self, line: &str) -> Result<Contract, Error> {
+        let bytecode = self.compile(line)?;
+        Ok(Contract::new(&bytecode, self.options.fork))
+    }
+
+    /// Compile a line with and without the optimizer, and report what it saves
+    pub fn report(&self, line: &str) -> Result<Report, Error> {
+        let (_, ast) = self.parse_line(line)?;
+        let constants = self.constants();
+        let compile = |optimize| {
+            let options = Options {
+                optimize,
+                ..self.options
+            };
+            codegen::compile_with_constants(&ast, &options, &constants)
+        };
+        Ok(Report::new(
+            &compile(false)?,
+            &compile(true)?,
+            &self.options,
+        ))
+    }
+
+    /// Whether a line assigns its result to a name, e.g. `fee = 21000 * 3`
+    pub fn is_assignment(&self, line: &str) -> bool {
+        lexer::lexer(line).is_ok_and(|tokens| assignment(tokens).0.is_some())
+    }
+
+    /// Parse the calculation of a line, leaving out the name it assigns if any
+    pub fn parse(&self, line: &str) -> Result<Expr, Error> {
+        self.parse_line(line).map(|(_, ast)| ast)
+    }
+
+    /// Parse a line, and return the name it assigns if any
+    fn parse_line(&self, line: &str) -> Result<(Option<String>, Expr), Error> {
+        let tokens = lexer::lexer(line)?;
+        let (name, tokens) = assignment(tokens);
+        if let Some(name) = &name {
+            if ANS.contains(&name.node.as_str()) || name.node.starts_with('$') {
+                return Err(CompileError::ReservedName(name.node.clone(), name.span).into());
+            }
+        }
+        let ast = parser::parse(tokens)?;
+        Ok((name.map(|n| n.node), ast))
+    }
+
+    /// Compile a line, and return the name it assigns if any
+    fn compile_line(&self, line: &str) -> Result<(Option<String>, Bytecode), Error> {
+        let (name, ast) = self.parse_line(line)?;
+        let bytecode = codegen::compile_with_constants(&ast, &self.options, &self.constants())?;
+        Ok((name, bytecode))
+    }
+
+    /// Values of the names defined by the session
+    fn constants(&self) -> HashMap<String, Uint> {
+        let mut constants = self.variables.clone();
+        let history = self.history.iter().enumerate();
+        constants.extend(history.map(|(i, value)| (format!("${}", i + 1), *value)));
+        if let Some(ans) = self.ans() {
+            constants.extend(ANS.map(|name| (name.to_owned(), ans)));
+        }
+        constants
+    }
+}
+
+/// Split `name = calculation` into the name and the tokens of the calculation
+fn assignment(tokens: TokenList) -> (Option<Spanned<String>>, TokenList) {
+    let mut significant = tokens[..].iter().filter(|t| t.node != Token::Whitespace);
+    let (name, assign) = match (significant.next(), significant.next()) {
+        (
+            Some(Spanned {
+                node: Token::Ident(name),
+                span,
+            }),
+            Some(Spanned {
+                node: Token::Assign,
+                span: assign,
+            }),
+        ) => (Spanned::new(name.clone(), *span), *assign),
+        _ => return (None, tokens),
+    };
+    let rest = tokens
+        .into_iter()
+        .filter(|t| t.span.start > assign.start)
+        .collect::<Vec<_>>();
+    (Some(name), rest.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::span::Span;
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::default();
+        let value = |s: &mut Session, line: &str| s.eval(line).map(|c| c.value);
+        assert_eq!(session.ans(), None);
+        assert_eq!(
+            value(&mut session, "ans"),
+            Err(Error::Compile(CompileError::UndefinedVariable(
+                "ans".to_owned(),
+                Span::new(0, 3)
+            )))
+        );
+
+        assert_eq!(value(&mut session, "1 + 2"), Ok(Uint::from(3)));
+        assert_eq!(value(&mut session, "ans * 2"), Ok(Uint::from(6)));
+        assert_eq!(value(&mut session, "_ + $1"), Ok(Uint::from(9)));
+        assert_eq!(value(&mut session, "$2"), Ok(Uint::from(6)));
+        assert_eq!(session.ans(), Some(Uint::from(6)));
+        assert!(value(&mut session, "$9").is_err());
+        // Errors aren't part of the history
+        assert_eq!(session.history.len(), 4);
+
+        // Assignments
+        assert_eq!(
+            value(&mut session, "fee = 21000 * 3"),
+            Ok(Uint::from(63000))
+        );
+        assert_eq!(value(&mut session, "fee / 1000"), Ok(Uint::from(63)));
+        assert_eq!(value(&mut session, "fee = fee + 1"), Ok(Uint::from(63001)));
+        assert_eq!(value(&mut session, "let fee = 1; fee"), Ok(Uint::one()));
+        assert_eq!(value(&mut session, "fee"), Ok(Uint::from(63001)));
+        assert_eq!(session.history.len(), 9);
+
+        assert_eq!(
+            value(&mut session, " ans = 1"),
+            Err(Error::Compile(CompileError::ReservedName(
+                "ans".to_owned(),
+                Span::new(1, 4)
+            )))
+        );
+        assert_eq!(
+            value(&mut session, "x = "),
+            Err(Error::Lexical(
+                crate::utils::errors::LexicalError::UnexpectedEnd(Span::new(4, 4))
+            ))
+        );
+        assert!(!session.variables.contains_key("x"));
+        assert!(session.is_assignment("x = 1"));
+        assert!(!session.is_assignment("x == 1"));
+        assert!(!session.is_assignment("let x = 1; x"));
+        assert_eq!(
+            session.parse("y = 1 + 2").map(|e| e.to_sexp()),
+            Ok("(add 1 2)".to_owned())
+        );
+
+        assert_eq!(
+            session
+                .report("fee * 2")
+                .map(|r| (r.size_before, r.size_after)),
+            Ok((6, 4))
+        );
+        assert_eq!(
+            session
+                .report("fee * (1 + 1)")
+                .map(|r| (r.size_before, r.size_after)),
+            Ok((9, 4))
+        );
+        // The values of the session are folded, unless a let binds the name
+        session.options.optimize = true;
+        assert_eq!(session.compile("fee * (1 + 1)"), session.compile("126002"));
+        assert_eq!(
+            session.compile("let fee = 2; fee * 3"),
+            session.compile("let x = 2; x * 3")
+        );
+        session.options.optimize = false;
+
+        assert_eq!(session.format(Uint::MAX), Uint::MAX.to_string());
+        session.format = ResultFormat::Hex;
+        assert_eq!(session.format(Uint::from(255)), "0xff");
+
+        // The options apply to every line
+        session.options.mode = Mode::Signed;
+        assert_eq!(session.format(Uint::MAX), format!("0x{}", "f".repeat(64)));
+        session.format = ResultFormat::Decimal;
+        assert_eq!(session.format(Uint::MAX), "-1");
+        assert_eq!(
+            value(&mut session, "-8 / 2"),
+            Ok(crate::I256::from(-4).into_raw())
+        );
+    }
+}
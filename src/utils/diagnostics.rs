@@ -0,0 +1,154 @@
+use std::fmt::Display;
+
+use crate::utils::{errors::Error, span::Span};
+
+/// Where a source comes from, e.g. the second line of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin<'a> {
+    /// Name of the input, e.g. a file name or `stdin`, if any
+    pub input: Option<&'a str>,
+    /// Number of the first line of the source in the input, from 1
+    pub line: usize,
+}
+
+impl Default for Origin<'_> {
+    fn default() -> Self {
+        Origin {
+            input: None,
+            line: 1,
+        }
+    }
+}
+
+/// Render `message` with the source line `span` starts on, underlining the span,
+/// the line and the column being the ones in the input the source comes from
+///
+/// ```text
+/// error: Invalid character: @
+///  --> calc.txt:4:15
+///   |
+/// 4 | (156 + 4) * 3 @ 2
+///   |               ^
+/// ```
+pub fn render(source: &str, origin: Origin, span: Span, message: impl Display) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line = &source[line_start..line_end];
+    let line_number = source[..line_start].matches('\n').count() + origin.line;
+    // Columns are counted in chars so that the caret lines up with the source
+    let column = source[line_start..start].chars().count();
+    let width = source
+        .get(start..span.end.clamp(start, line_end))
+        .map_or(0, |s| s.chars().count())
+        .max(1);
+    let gutter = " ".repeat(line_number.to_string().len());
+    let input = origin
+        .input
+        .map_or(String::new(), |input| format!("{}:", input));
+
+    format!(
+        "error: {message}\n{gutter}--> {input}{line_number}:{}\n{gutter} |\n{line_number} | {line}\n{gutter} | {}{}",
+        column + 1,
+        " ".repeat(column),
+        "^".repeat(width),
+    )
+}
+
+/// Render an error, pointing at the source when it has a span,
+/// and at the line of the input otherwise
+pub fn report(source: &str, origin: Origin, error: &Error) -> String {
+    match (error.span(), origin.input) {
+        (Some(span), _) => render(source, origin, span, error),
+        (None, Some(input)) => format!("error: {}\n --> {}:{}", error, input, origin.line),
+        (None, None) => format!("error: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate, calculate_with, Options};
+
+    #[test]
+    fn test_render() {
+        let source = "(156 + 4) * 3 @ 2";
+        let expected = [
+            "error: Invalid character: @",
+            " --> 1:15",
+            "  |",
+            "1 | (156 + 4) * 3 @ 2",
+            "  |               ^",
+        ]
+        .join("\n");
+        assert_eq!(
+            report(source, Origin::default(), &calculate(source).unwrap_err()),
+            expected
+        );
+
+        // Multibyte chars and ranges
+        let source = "é + foo(1)";
+        let expected = [
+            "error: boom",
+            " --> 1:5",
+            "  |",
+            "1 | é + foo(1)",
+            "  |     ^^^^^^",
+        ]
+        .join("\n");
+        assert_eq!(
+            render(source, Origin::default(), Span::new(5, 11), "boom"),
+            expected
+        );
+
+        // Second line
+        let source = "1 +\n2 *";
+        let expected = [
+            "error: Missing operand after '*'",
+            " --> 2:3",
+            "  |",
+            "2 | 2 *",
+            "  |   ^",
+        ]
+        .join("\n");
+        assert_eq!(
+            report(source, Origin::default(), &calculate(source).unwrap_err()),
+            expected
+        );
+
+        // Span at the end of the input
+        let expected = ["error: eof", " --> 1:4", "  |", "1 | 1 +", "  |    ^"].join("\n");
+        assert_eq!(
+            render("1 +", Origin::default(), Span::new(3, 3), "eof"),
+            expected
+        );
+
+        // Line of a file
+        let origin = Origin {
+            input: Some("calc.txt"),
+            line: 12,
+        };
+        let expected = [
+            "error: eof",
+            "  --> calc.txt:12:4",
+            "   |",
+            "12 | 1 +",
+            "   |    ^",
+        ]
+        .join("\n");
+        assert_eq!(render("1 +", origin, Span::new(3, 3), "eof"), expected);
+        let error = calculate_with(
+            "1 + 2",
+            &Options {
+                gas_limit: 1,
+                ..Options::default()
+            },
+        );
+        assert_eq!(
+            report("1 + 2", origin, &error.unwrap_err()),
+            "error: Execution error: out of gas at offset 0 (gas limit 1)\n --> calc.txt:12"
+        );
+    }
+}
@@ -1,28 +1,241 @@
 use std::fmt::Display;
 
-use crate::lexer::TokenList;
+use ethers_core::{abi::Uint, types::Bytes};
+use thiserror::Error;
+
+use crate::{
+    lexer::NumberError,
+    parser::{MAX_CHAIN, MAX_DEPTH},
+    utils::{span::Span, stack::StackError, tokens::Token},
+};
 
 /// A lexical error
 #[derive(Debug, PartialEq, Eq)]
 pub enum LexicalError {
     /// Invalid characters
-    InvalidCharacter(char),
-    /// Invalid number
-    InvalidNumber(String),
+    InvalidCharacter(char, Span),
+    /// Invalid number, with why it is invalid
+    InvalidNumber(String, NumberError, Span),
     /// Mismatched parenthesis
-    MismatchedParenthesis,
-    /// Invalid token list
-    InvalidTokenList(TokenList),
+    MismatchedParenthesis(Span),
+    /// Token that doesn't fit where it is
+    UnexpectedToken(Token, Span),
+    /// Operator without its right operand
+    MissingOperand(Token, Span),
+    /// Infix operator without its left operand
+    MissingLeftOperand(Token, Span),
+    /// Parentheses with nothing in between
+    EmptyParentheses(Span),
+    /// Source ending in the middle of an expression
+    UnexpectedEnd(Span),
+    /// Something else than what the grammar requires, e.g. a name after `let`
+    Expected(String, Span),
+    /// Expression nested deeper than the parser allows
+    TooDeep(Span),
+    /// Chain of operations longer than the parser allows
+    TooLong(Span),
+}
+
+impl LexicalError {
+    /// Span of the characters or tokens at fault
+    pub fn span(&self) -> Span {
+        match self {
+            LexicalError::InvalidCharacter(_, span)
+            | LexicalError::InvalidNumber(_, _, span)
+            | LexicalError::MismatchedParenthesis(span)
+            | LexicalError::UnexpectedToken(_, span)
+            | LexicalError::MissingOperand(_, span)
+            | LexicalError::MissingLeftOperand(_, span)
+            | LexicalError::EmptyParentheses(span)
+            | LexicalError::UnexpectedEnd(span)
+            | LexicalError::Expected(_, span)
+            | LexicalError::TooDeep(span)
+            | LexicalError::TooLong(span) => *span,
+        }
+    }
 }
 
 impl Display for LexicalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let e = match &self {
-            &LexicalError::InvalidCharacter(c) => format!("Invalid character: {}", c),
-            &LexicalError::InvalidNumber(n) => format!("Invalid number: {}", n),
-            &LexicalError::MismatchedParenthesis => String::from("Mismatched parenthesis"),
-            &LexicalError::InvalidTokenList(t) => format!("Invalid source: {}", t),
+        let e = match self {
+            LexicalError::InvalidCharacter(c, _) => format!("Invalid character: {}", c),
+            LexicalError::InvalidNumber(n, cause, _) => match cause {
+                NumberError::Malformed => format!("Invalid number: {}", n),
+                NumberError::Overflow => {
+                    format!("Invalid number: {} is larger than 2^256 - 1", n)
+                }
+                NumberError::Fractional => {
+                    format!("Invalid number: {} is not a whole number of wei", n)
+                }
+                NumberError::UnitWithPrefix => {
+                    format!("Invalid number: {} has a unit but isn't decimal", n)
+                }
+            },
+            LexicalError::MismatchedParenthesis(_) => String::from("Mismatched parenthesis"),
+            LexicalError::UnexpectedToken(Token::Num(_), _) => String::from("Unexpected number"),
+            LexicalError::UnexpectedToken(Token::Ident(name), _) => {
+                format!("Unexpected name: {}", name)
+            }
+            LexicalError::UnexpectedToken(t, _) => format!("Unexpected token: {}", t),
+            LexicalError::MissingOperand(t, _) => format!("Missing operand after '{}'", t),
+            LexicalError::MissingLeftOperand(t, _) => format!("Missing operand before '{}'", t),
+            LexicalError::EmptyParentheses(_) => String::from("Empty parentheses"),
+            LexicalError::UnexpectedEnd(_) => String::from("Unexpected end of input"),
+            LexicalError::Expected(what, _) => format!("Expected {}", what),
+            LexicalError::TooDeep(_) => {
+                format!("Expression nested more than {} levels deep", MAX_DEPTH)
+            }
+            LexicalError::TooLong(_) => {
+                format!("Expression chaining more than {} operations", MAX_CHAIN)
+            }
         };
         write!(f, "{}", e)
     }
 }
+
+impl std::error::Error for LexicalError {}
+
+/// An error raised while generating bytecode
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CompileError {
+    /// Reference to a name that isn't defined
+    #[error("undefined variable: {0}")]
+    UndefinedVariable(String, Span),
+    /// Call to a function that doesn't exist
+    #[error("unknown function: {0}")]
+    UnknownFunction(String, Span),
+    /// Assignment to a name the session defines, e.g. `ans`
+    #[error("{0} is reserved")]
+    ReservedName(String, Span),
+    /// Reference to a variable bound too far down the stack for DUP16
+    #[error("{0} is too deep in the stack to be reached")]
+    StackTooDeep(String, Span),
+    /// Call with the wrong number of arguments
+    #[error("{name} takes {expected} arguments but {found} were given")]
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// Conditional whose code ends past the offsets a jump can reach
+    #[error("code is too large to jump to offset {0}")]
+    CodeTooLarge(usize, Span),
+}
+
+impl CompileError {
+    /// Span of the expression at fault
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::UndefinedVariable(_, span)
+            | CompileError::UnknownFunction(_, span)
+            | CompileError::StackTooDeep(_, span)
+            | CompileError::ReservedName(_, span)
+            | CompileError::CodeTooLarge(_, span)
+            | CompileError::ArgumentCount { span, .. } => *span,
+        }
+    }
+}
+
+/// An error raised while assembling mnemonics
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AssemblyError {
+    /// A word that isn't a known mnemonic
+    #[error("unknown mnemonic: {0}")]
+    UnknownMnemonic(String, Span),
+    /// An instruction without the immediate it takes
+    #[error("{0} takes an immediate")]
+    MissingImmediate(String, Span),
+    /// An immediate that isn't a number or a label
+    #[error("invalid immediate: {0}")]
+    InvalidImmediate(String, Span),
+    /// An immediate that doesn't fit the instruction
+    #[error("{value} doesn't fit in {size} bytes")]
+    ImmediateTooLarge {
+        value: String,
+        size: usize,
+        span: Span,
+    },
+    /// A label defined twice
+    #[error("duplicate label: {0}")]
+    DuplicateLabel(String, Span),
+    /// A reference to a label that isn't defined
+    #[error("undefined label: {0}")]
+    UndefinedLabel(String, Span),
+}
+
+impl AssemblyError {
+    /// Span of the assembly word at fault
+    pub fn span(&self) -> Span {
+        match self {
+            AssemblyError::UnknownMnemonic(_, span)
+            | AssemblyError::MissingImmediate(_, span)
+            | AssemblyError::InvalidImmediate(_, span)
+            | AssemblyError::ImmediateTooLarge { span, .. }
+            | AssemblyError::DuplicateLabel(_, span)
+            | AssemblyError::UndefinedLabel(_, span) => *span,
+        }
+    }
+}
+
+/// An error raised while decoding hexadecimal bytecode
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// A character that isn't a hexadecimal digit, at the given index
+    #[error("invalid hex character {character:?} at index {index}")]
+    InvalidCharacter { character: char, index: usize },
+    /// An odd number of hexadecimal digits
+    #[error("odd number of hex digits: {0}")]
+    OddLength(usize),
+}
+
+/// An error raised while executing bytecode
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExecutionError {
+    /// Unknown opcode at the given byte offset
+    #[error("invalid opcode 0x{opcode:02x} at offset {offset}")]
+    InvalidOpcode { offset: usize, opcode: u8 },
+    /// A PUSH instruction whose immediate runs past the end of the bytecode
+    #[error("truncated immediate for the instruction at offset {offset}")]
+    TruncatedImmediate { offset: usize },
+    /// A JUMP or JUMPI at the given byte offset to something else than a JUMPDEST
+    #[error("invalid jump destination {destination:#x} at offset {offset}")]
+    InvalidJump { offset: usize, destination: Uint },
+    /// The instruction at the given byte offset needs more gas than there is left
+    #[error("out of gas at offset {offset} (gas limit {gas_limit})")]
+    OutOfGas { offset: usize, gas_limit: u64 },
+    /// Stack overflow or underflow
+    #[error(transparent)]
+    Stack(#[from] StackError),
+    /// The execution ended without any value on the stack
+    #[error("execution ended with an empty stack")]
+    EmptyStack,
+    /// RETURN of something else than a word
+    #[error("returned {0} bytes instead of a 32-byte word")]
+    InvalidReturn(usize),
+    /// REVERT, with the data it returns
+    #[error("execution reverted with {0}")]
+    Reverted(Bytes),
+}
+
+/// Any error that can occur while calculating
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error(transparent)]
+    Lexical(#[from] LexicalError),
+    #[error("Compile error: {0}")]
+    Compile(#[from] CompileError),
+    #[error("Execution error: {0}")]
+    Execution(#[from] ExecutionError),
+}
+
+impl Error {
+    /// Span of the source at fault, `None` for execution errors
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Lexical(e) => Some(e.span()),
+            Error::Compile(e) => Some(e.span()),
+            Error::Execution(_) => None,
+        }
+    }
+}
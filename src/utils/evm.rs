@@ -1,17 +1,125 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::HashSet, fmt::Display, str::FromStr};
 
-use ethers_core::abi::Uint;
+use ethers_core::{
+    abi::{ethereum_types::U512, Uint},
+    types::{Bytes, I256},
+};
 
-use crate::{codegen::Bytecode, utils::stack::Stack};
+use crate::{
+    codegen::Bytecode,
+    utils::{
+        errors::ExecutionError,
+        gas::{self, Fork, Gas},
+        memory::{Memory, WORD},
+        stack::{Stack, StackError},
+        tracer::Tracer,
+    },
+};
 
 /// EVM Opcodes
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Opcode {
+    STOP = 0x00,
     ADD = 0x01,
     MUL = 0x02,
     SUB = 0x03,
     DIV = 0x04,
+    SDIV = 0x05,
+    MOD = 0x06,
+    SMOD = 0x07,
+    ADDMOD = 0x08,
+    MULMOD = 0x09,
+    EXP = 0x0A,
+    LT = 0x10,
+    GT = 0x11,
+    SLT = 0x12,
+    SGT = 0x13,
+    EQ = 0x14,
+    ISZERO = 0x15,
+    AND = 0x16,
+    OR = 0x17,
+    XOR = 0x18,
+    NOT = 0x19,
+    SHL = 0x1B,
+    SHR = 0x1C,
+    SAR = 0x1D,
+    CODECOPY = 0x39,
+    POP = 0x50,
+    MLOAD = 0x51,
+    MSTORE = 0x52,
+    MSTORE8 = 0x53,
+    JUMP = 0x56,
+    JUMPI = 0x57,
+    PC = 0x58,
+    MSIZE = 0x59,
+    JUMPDEST = 0x5B,
+    PUSH0 = 0x5F,
+    PUSH1 = 0x60,
+    PUSH2 = 0x61,
+    PUSH3 = 0x62,
+    PUSH4 = 0x63,
+    PUSH5 = 0x64,
+    PUSH6 = 0x65,
+    PUSH7 = 0x66,
+    PUSH8 = 0x67,
+    PUSH9 = 0x68,
+    PUSH10 = 0x69,
+    PUSH11 = 0x6A,
+    PUSH12 = 0x6B,
+    PUSH13 = 0x6C,
+    PUSH14 = 0x6D,
+    PUSH15 = 0x6E,
+    PUSH16 = 0x6F,
+    PUSH17 = 0x70,
+    PUSH18 = 0x71,
+    PUSH19 = 0x72,
+    PUSH20 = 0x73,
+    PUSH21 = 0x74,
+    PUSH22 = 0x75,
+    PUSH23 = 0x76,
+    PUSH24 = 0x77,
+    PUSH25 = 0x78,
+    PUSH26 = 0x79,
+    PUSH27 = 0x7A,
+    PUSH28 = 0x7B,
+    PUSH29 = 0x7C,
+    PUSH30 = 0x7D,
+    PUSH31 = 0x7E,
     PUSH32 = 0x7F,
+    DUP1 = 0x80,
+    DUP2 = 0x81,
+    DUP3 = 0x82,
+    DUP4 = 0x83,
+    DUP5 = 0x84,
+    DUP6 = 0x85,
+    DUP7 = 0x86,
+    DUP8 = 0x87,
+    DUP9 = 0x88,
+    DUP10 = 0x89,
+    DUP11 = 0x8A,
+    DUP12 = 0x8B,
+    DUP13 = 0x8C,
+    DUP14 = 0x8D,
+    DUP15 = 0x8E,
+    DUP16 = 0x8F,
+    SWAP1 = 0x90,
+    SWAP2 = 0x91,
+    SWAP3 = 0x92,
+    SWAP4 = 0x93,
+    SWAP5 = 0x94,
+    SWAP6 = 0x95,
+    SWAP7 = 0x96,
+    SWAP8 = 0x97,
+    SWAP9 = 0x98,
+    SWAP10 = 0x99,
+    SWAP11 = 0x9A,
+    SWAP12 = 0x9B,
+    SWAP13 = 0x9C,
+    SWAP14 = 0x9D,
+    SWAP15 = 0x9E,
+    SWAP16 = 0x9F,
+    RETURN = 0xF3,
+    REVERT = 0xFD,
 }
 
 impl Display for Opcode {
@@ -25,60 +133,756 @@ pub enum OpcodeError {
     InvalidOpcode(String),
 }
 
+impl TryFrom<u8> for Opcode {
+    type Error = OpcodeError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0x00 => Ok(Opcode::STOP),
+            0x01 => Ok(Opcode::ADD),
+            0x02 => Ok(Opcode::MUL),
+            0x03 => Ok(Opcode::SUB),
+            0x04 => Ok(Opcode::DIV),
+            0x05 => Ok(Opcode::SDIV),
+            0x06 => Ok(Opcode::MOD),
+            0x07 => Ok(Opcode::SMOD),
+            0x08 => Ok(Opcode::ADDMOD),
+            0x09 => Ok(Opcode::MULMOD),
+            0x0A => Ok(Opcode::EXP),
+            0x10 => Ok(Opcode::LT),
+            0x11 => Ok(Opcode::GT),
+            0x12 => Ok(Opcode::SLT),
+            0x13 => Ok(Opcode::SGT),
+            0x14 => Ok(Opcode::EQ),
+            0x15 => Ok(Opcode::ISZERO),
+            0x16 => Ok(Opcode::AND),
+            0x17 => Ok(Opcode::OR),
+            0x18 => Ok(Opcode::XOR),
+            0x19 => Ok(Opcode::NOT),
+            0x1B => Ok(Opcode::SHL),
+            0x1C => Ok(Opcode::SHR),
+            0x1D => Ok(Opcode::SAR),
+            0x39 => Ok(Opcode::CODECOPY),
+            0x50 => Ok(Opcode::POP),
+            0x51 => Ok(Opcode::MLOAD),
+            0x52 => Ok(Opcode::MSTORE),
+            0x53 => Ok(Opcode::MSTORE8),
+            0x56 => Ok(Opcode::JUMP),
+            0x57 => Ok(Opcode::JUMPI),
+            0x58 => Ok(Opcode::PC),
+            0x59 => Ok(Opcode::MSIZE),
+            0x5B => Ok(Opcode::JUMPDEST),
+            0x5F => Ok(Opcode::PUSH0),
+            0x60 => Ok(Opcode::PUSH1),
+            0x61 => Ok(Opcode::PUSH2),
+            0x62 => Ok(Opcode::PUSH3),
+            0x63 => Ok(Opcode::PUSH4),
+            0x64 => Ok(Opcode::PUSH5),
+            0x65 => Ok(Opcode::PUSH6),
+            0x66 => Ok(Opcode::PUSH7),
+            0x67 => Ok(Opcode::PUSH8),
+            0x68 => Ok(Opcode::PUSH9),
+            0x69 => Ok(Opcode::PUSH10),
+            0x6A => Ok(Opcode::PUSH11),
+            0x6B => Ok(Opcode::PUSH12),
+            0x6C => Ok(Opcode::PUSH13),
+            0x6D => Ok(Opcode::PUSH14),
+            0x6E => Ok(Opcode::PUSH15),
+            0x6F => Ok(Opcode::PUSH16),
+            0x70 => Ok(Opcode::PUSH17),
+            0x71 => Ok(Opcode::PUSH18),
+            0x72 => Ok(Opcode::PUSH19),
+            0x73 => Ok(Opcode::PUSH20),
+            0x74 => Ok(Opcode::PUSH21),
+            0x75 => Ok(Opcode::PUSH22),
+            0x76 => Ok(Opcode::PUSH23),
+            0x77 => Ok(Opcode::PUSH24),
+            0x78 => Ok(Opcode::PUSH25),
+            0x79 => Ok(Opcode::PUSH26),
+            0x7A => Ok(Opcode::PUSH27),
+            0x7B => Ok(Opcode::PUSH28),
+            0x7C => Ok(Opcode::PUSH29),
+            0x7D => Ok(Opcode::PUSH30),
+            0x7E => Ok(Opcode::PUSH31),
+            0x7F => Ok(Opcode::PUSH32),
+            0x80 => Ok(Opcode::DUP1),
+            0x81 => Ok(Opcode::DUP2),
+            0x82 => Ok(Opcode::DUP3),
+            0x83 => Ok(Opcode::DUP4),
+            0x84 => Ok(Opcode::DUP5),
+            0x85 => Ok(Opcode::DUP6),
+            0x86 => Ok(Opcode::DUP7),
+            0x87 => Ok(Opcode::DUP8),
+            0x88 => Ok(Opcode::DUP9),
+            0x89 => Ok(Opcode::DUP10),
+            0x8A => Ok(Opcode::DUP11),
+            0x8B => Ok(Opcode::DUP12),
+            0x8C => Ok(Opcode::DUP13),
+            0x8D => Ok(Opcode::DUP14),
+            0x8E => Ok(Opcode::DUP15),
+            0x8F => Ok(Opcode::DUP16),
+            0x90 => Ok(Opcode::SWAP1),
+            0x91 => Ok(Opcode::SWAP2),
+            0x92 => Ok(Opcode::SWAP3),
+            0x93 => Ok(Opcode::SWAP4),
+            0x94 => Ok(Opcode::SWAP5),
+            0x95 => Ok(Opcode::SWAP6),
+            0x96 => Ok(Opcode::SWAP7),
+            0x97 => Ok(Opcode::SWAP8),
+            0x98 => Ok(Opcode::SWAP9),
+            0x99 => Ok(Opcode::SWAP10),
+            0x9A => Ok(Opcode::SWAP11),
+            0x9B => Ok(Opcode::SWAP12),
+            0x9C => Ok(Opcode::SWAP13),
+            0x9D => Ok(Opcode::SWAP14),
+            0x9E => Ok(Opcode::SWAP15),
+            0x9F => Ok(Opcode::SWAP16),
+            0xF3 => Ok(Opcode::RETURN),
+            0xFD => Ok(Opcode::REVERT),
+            _ => Err(OpcodeError::InvalidOpcode(format!("{:02X}", byte))),
+        }
+    }
+}
+
 impl FromStr for Opcode {
     type Err = OpcodeError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "01" => Ok(Opcode::ADD),
-            "02" => Ok(Opcode::MUL),
-            "03" => Ok(Opcode::SUB),
-            "04" => Ok(Opcode::DIV),
-            "7F" => Ok(Opcode::PUSH32),
-            _ => Err(OpcodeError::InvalidOpcode(s.to_owned())),
+        if s.len() != 2 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(OpcodeError::InvalidOpcode(s.to_owned()));
         }
+        // Safe unwrap here
+        Opcode::try_from(u8::from_str_radix(s, 16).unwrap())
     }
 }
 
+impl Opcode {
+    /// PUSH opcode for an immediate of `size` bytes
+    pub fn push(size: usize) -> Option<Opcode> {
+        u8::try_from(size)
+            .ok()
+            .filter(|&size| size <= 32)
+            .and_then(|size| Opcode::try_from(Opcode::PUSH0 as u8 + size).ok())
+    }
+
+    /// DUP opcode copying the `n`th value from the top
+    pub fn dup(n: usize) -> Option<Opcode> {
+        u8::try_from(n)
+            .ok()
+            .filter(|&n| (1..=16).contains(&n))
+            .and_then(|n| Opcode::try_from(Opcode::DUP1 as u8 - 1 + n).ok())
+    }
+
+    /// SWAP opcode exchanging the top and the `n + 1`th values
+    pub fn swap(n: usize) -> Option<Opcode> {
+        u8::try_from(n)
+            .ok()
+            .filter(|&n| (1..=16).contains(&n))
+            .and_then(|n| Opcode::try_from(Opcode::SWAP1 as u8 - 1 + n).ok())
+    }
+
+    /// Look up an opcode by its mnemonic, in any case
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        (0..=u8::MAX)
+            .filter_map(|byte| Opcode::try_from(byte).ok())
+            .find(|opcode| format!("{:?}", opcode).eq_ignore_ascii_case(mnemonic))
+    }
+
+    /// Size in bytes of the immediate following the opcode
+    pub fn immediate_size(&self) -> usize {
+        match *self as u8 {
+            byte @ 0x5F..=0x7F => (byte - Opcode::PUSH0 as u8) as usize,
+            _ => 0,
+        }
+    }
+
+    /// Number of values the opcode takes from the stack, and number of values it pushes
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match *self as u8 {
+            // DUPn and SWAPn
+            byte @ 0x80..=0x8F => {
+                let n = (byte - Opcode::DUP1 as u8 + 1) as usize;
+                (n, n + 1)
+            }
+            byte @ 0x90..=0x9F => {
+                let n = (byte - Opcode::SWAP1 as u8 + 1) as usize;
+                (n + 1, n + 1)
+            }
+            _ => match self {
+                Opcode::STOP | Opcode::JUMPDEST => (0, 0),
+                Opcode::PC | Opcode::MSIZE => (0, 1),
+                Opcode::ISZERO | Opcode::NOT | Opcode::MLOAD => (1, 1),
+                Opcode::POP | Opcode::JUMP => (1, 0),
+                Opcode::MSTORE
+                | Opcode::MSTORE8
+                | Opcode::JUMPI
+                | Opcode::RETURN
+                | Opcode::REVERT => (2, 0),
+                Opcode::ADDMOD | Opcode::MULMOD => (3, 1),
+                Opcode::CODECOPY => (3, 0),
+                _ if self.immediate_size() > 0 || *self == Opcode::PUSH0 => (0, 1),
+                // The arithmetic, comparison, bitwise and shift operations
+                _ => (2, 1),
+            },
+        }
+    }
+}
+
+/// A decoded instruction of some bytecode
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Instruction {
+    /// A known opcode with its immediate, if it takes one
+    Op {
+        offset: usize,
+        opcode: Opcode,
+        immediate: Vec<u8>,
+    },
+    /// A byte that isn't a known opcode
+    Invalid { offset: usize, byte: u8 },
+    /// A PUSH whose immediate runs past the end of the bytecode
+    Truncated {
+        offset: usize,
+        opcode: Opcode,
+        immediate: Vec<u8>,
+    },
+}
+
+impl Instruction {
+    /// Byte offset of the instruction
+    pub fn offset(&self) -> usize {
+        match self {
+            Instruction::Op { offset, .. }
+            | Instruction::Invalid { offset, .. }
+            | Instruction::Truncated { offset, .. } => *offset,
+        }
+    }
+}
+
+/// One line of assembly, e.g. `0x0000: PUSH1 0x02`
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06x}: ", self.offset())?;
+        match self {
+            Instruction::Op {
+                opcode, immediate, ..
+            } => {
+                write!(f, "{:?}", opcode)?;
+                if !immediate.is_empty() {
+                    write!(f, " {:#x}", Bytecode::from(immediate.clone()))?;
+                }
+                Ok(())
+            }
+            Instruction::Invalid { byte, .. } => write!(f, "INVALID {:#04x}", byte),
+            Instruction::Truncated {
+                opcode, immediate, ..
+            } => write!(
+                f,
+                "{:?} {:#x} ; truncated, {} of {} bytes",
+                opcode,
+                Bytecode::from(immediate.clone()),
+                immediate.len(),
+                opcode.immediate_size()
+            ),
+        }
+    }
+}
+
+/// Decode every instruction of some bytecode, including invalid ones
+pub fn disassemble(bytecode: &Bytecode) -> Vec<Instruction> {
+    let code = bytecode.as_ref();
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while let Some(&byte) = code.get(offset) {
+        let Ok(opcode) = Opcode::try_from(byte) else {
+            instructions.push(Instruction::Invalid { offset, byte });
+            offset += 1;
+            continue;
+        };
+        let start = offset + 1;
+        let end = start + opcode.immediate_size();
+        let instruction = match code.get(start..end) {
+            Some(immediate) => Instruction::Op {
+                offset,
+                opcode,
+                immediate: immediate.to_vec(),
+            },
+            None => Instruction::Truncated {
+                offset,
+                opcode,
+                immediate: code[start..].to_vec(),
+            },
+        };
+        instructions.push(instruction);
+        offset = end;
+    }
+    instructions
+}
+
+/// Offsets of the JUMPDEST instructions, the only valid jump destinations
+///
+/// A `0x5B` byte in the immediate of a PUSH is data, not a JUMPDEST.
+pub fn jump_destinations(bytecode: &Bytecode) -> HashSet<usize> {
+    disassemble(bytecode)
+        .into_iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Op {
+                offset,
+                opcode: Opcode::JUMPDEST,
+                ..
+            } => Some(offset),
+            _ => None,
+        })
+        .collect()
+}
+
+/// How an execution ended
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Outcome {
+    /// STOP or the end of the code, with the value left on top of the stack if any
+    Stop(Option<Uint>),
+    /// RETURN with the data it returns
+    Return(Bytes),
+    /// REVERT with the data it returns
+    Revert(Bytes),
+}
+
+impl Outcome {
+    /// Result of a calculation:
+    /// the value left on top of the stack, or the 32-byte word returned
+    pub fn value(&self) -> Result<Uint, ExecutionError> {
+        match self {
+            Outcome::Stop(top) => top.ok_or(ExecutionError::EmptyStack),
+            Outcome::Return(data) if data.as_ref().len() == WORD => {
+                Ok(Uint::from_big_endian(data.as_ref()))
+            }
+            Outcome::Return(data) => Err(ExecutionError::InvalidReturn(data.as_ref().len())),
+            Outcome::Revert(data) => Err(ExecutionError::Reverted(data.clone())),
+        }
+    }
+
+    /// Data returned, empty when the execution stopped
+    pub fn output(&self) -> &[u8] {
+        match self {
+            Outcome::Stop(_) => &[],
+            Outcome::Return(data) | Outcome::Revert(data) => data.as_ref(),
+        }
+    }
+}
+
+/// A mini EVM, the default one runs the latest fork with the block gas limit
+#[derive(Default)]
 pub struct EVM {
     pub stack: Stack<Uint>,
+    pub memory: Memory,
+    pub gas: Gas,
+    pub fork: Fork,
 }
 
 impl EVM {
-    /// Execute some bytecode
-    pub fn execute(bytecode: &Bytecode) -> Uint {
-        let mut stack = Stack::new();
-        let mut bytecode = &bytecode.to_string()[..];
-        while !bytecode.is_empty() {
-            let (op, tail) = bytecode.split_at(2);
-            bytecode = tail;
-            match op.parse().unwrap() {
-                Opcode::PUSH32 => {
-                    let (uint, rest) = tail.split_at(64);
-                    bytecode = rest;
-                    stack.push(Uint::from_str_radix(uint, 16).unwrap()).unwrap();
+    pub fn new(fork: Fork, gas_limit: u64) -> Self {
+        EVM {
+            stack: Stack::new(),
+            memory: Memory::new(),
+            gas: Gas::new(gas_limit),
+            fork,
+        }
+    }
+
+    /// Execute some bytecode on this EVM's stack and memory, charging its gas
+    pub fn execute(&mut self, bytecode: &Bytecode) -> Result<Outcome, ExecutionError> {
+        self.execute_traced(bytecode, &mut ())
+    }
+
+    /// Execute some bytecode, reporting each step and the end of the execution to `tracer`
+    pub fn execute_traced(
+        &mut self,
+        bytecode: &Bytecode,
+        tracer: &mut impl Tracer,
+    ) -> Result<Outcome, ExecutionError> {
+        let result = self.steps(bytecode, tracer);
+        tracer.on_end(self.gas.used, result.as_ref());
+        result
+    }
+
+    fn steps(
+        &mut self,
+        bytecode: &Bytecode,
+        tracer: &mut impl Tracer,
+    ) -> Result<Outcome, ExecutionError> {
+        let EVM {
+            stack,
+            memory,
+            gas,
+            fork,
+        } = self;
+        let code = bytecode.as_ref();
+        let destinations = jump_destinations(bytecode);
+        let mut pc = 0;
+        while let Some(&byte) = code.get(pc) {
+            let (op, cost) = Opcode::try_from(byte)
+                .ok()
+                .and_then(|op| Some((op, fork.static_cost(op)?)))
+                .ok_or(ExecutionError::InvalidOpcode {
+                    offset: pc,
+                    opcode: byte,
+                })?;
+            let cost = cost.saturating_add(dynamic_cost(op, stack.as_slice(), memory.size()));
+            tracer.on_step(
+                pc,
+                op,
+                stack.as_slice(),
+                memory.size(),
+                gas.remaining(),
+                cost,
+            );
+            charge(gas, cost, pc)?;
+            match op {
+                Opcode::STOP => break,
+                Opcode::PUSH0 => stack.push(Uint::zero())?,
+                Opcode::PUSH1
+                | Opcode::PUSH2
+                | Opcode::PUSH3
+                | Opcode::PUSH4
+                | Opcode::PUSH5
+                | Opcode::PUSH6
+                | Opcode::PUSH7
+                | Opcode::PUSH8
+                | Opcode::PUSH9
+                | Opcode::PUSH10
+                | Opcode::PUSH11
+                | Opcode::PUSH12
+                | Opcode::PUSH13
+                | Opcode::PUSH14
+                | Opcode::PUSH15
+                | Opcode::PUSH16
+                | Opcode::PUSH17
+                | Opcode::PUSH18
+                | Opcode::PUSH19
+                | Opcode::PUSH20
+                | Opcode::PUSH21
+                | Opcode::PUSH22
+                | Opcode::PUSH23
+                | Opcode::PUSH24
+                | Opcode::PUSH25
+                | Opcode::PUSH26
+                | Opcode::PUSH27
+                | Opcode::PUSH28
+                | Opcode::PUSH29
+                | Opcode::PUSH30
+                | Opcode::PUSH31
+                | Opcode::PUSH32 => {
+                    let size = op.immediate_size();
+                    let immediate = code
+                        .get(pc + 1..pc + 1 + size)
+                        .ok_or(ExecutionError::TruncatedImmediate { offset: pc })?;
+                    stack.push(Uint::from_big_endian(immediate))?;
                 }
-                Opcode::ADD => {
-                    let res = stack.pop().unwrap() + stack.pop().unwrap();
-                    stack.push(res).unwrap();
+                Opcode::ADD => binary(stack, add)?,
+                Opcode::MUL => binary(stack, mul)?,
+                Opcode::SUB => binary(stack, sub)?,
+                Opcode::DIV => binary(stack, div)?,
+                Opcode::SDIV => binary(stack, sdiv)?,
+                Opcode::MOD => binary(stack, modulo)?,
+                Opcode::SMOD => binary(stack, smod)?,
+                Opcode::ADDMOD => ternary(stack, addmod)?,
+                Opcode::MULMOD => ternary(stack, mulmod)?,
+                Opcode::EXP => binary(stack, exp)?,
+                Opcode::LT => binary(stack, |a, b| Uint::from((a < b) as u8))?,
+                Opcode::GT => binary(stack, |a, b| Uint::from((a > b) as u8))?,
+                Opcode::SLT => binary(stack, slt)?,
+                Opcode::SGT => binary(stack, sgt)?,
+                Opcode::EQ => binary(stack, |a, b| Uint::from((a == b) as u8))?,
+                Opcode::ISZERO => unary(stack, |a| Uint::from(a.is_zero() as u8))?,
+                Opcode::AND => binary(stack, |a, b| a & b)?,
+                Opcode::OR => binary(stack, |a, b| a | b)?,
+                Opcode::XOR => binary(stack, |a, b| a ^ b)?,
+                Opcode::NOT => unary(stack, |a| !a)?,
+                Opcode::SHL => binary(stack, shl)?,
+                Opcode::SHR => binary(stack, shr)?,
+                Opcode::SAR => binary(stack, sar)?,
+                Opcode::POP => {
+                    stack.pop()?;
                 }
-                Opcode::MUL => {
-                    let res = stack.pop().unwrap() * stack.pop().unwrap();
-                    stack.push(res).unwrap();
+                Opcode::DUP1
+                | Opcode::DUP2
+                | Opcode::DUP3
+                | Opcode::DUP4
+                | Opcode::DUP5
+                | Opcode::DUP6
+                | Opcode::DUP7
+                | Opcode::DUP8
+                | Opcode::DUP9
+                | Opcode::DUP10
+                | Opcode::DUP11
+                | Opcode::DUP12
+                | Opcode::DUP13
+                | Opcode::DUP14
+                | Opcode::DUP15
+                | Opcode::DUP16 => stack.dup((op as u8 - Opcode::DUP1 as u8 + 1) as usize)?,
+                Opcode::SWAP1
+                | Opcode::SWAP2
+                | Opcode::SWAP3
+                | Opcode::SWAP4
+                | Opcode::SWAP5
+                | Opcode::SWAP6
+                | Opcode::SWAP7
+                | Opcode::SWAP8
+                | Opcode::SWAP9
+                | Opcode::SWAP10
+                | Opcode::SWAP11
+                | Opcode::SWAP12
+                | Opcode::SWAP13
+                | Opcode::SWAP14
+                | Opcode::SWAP15
+                | Opcode::SWAP16 => stack.swap((op as u8 - Opcode::SWAP1 as u8 + 1) as usize)?,
+                // The gas charged for the memory bounds the offsets, they fit in a usize
+                Opcode::MLOAD => {
+                    let offset = stack.pop()?;
+                    stack.push(memory.load(offset.as_usize()))?;
                 }
-                Opcode::SUB => {
-                    let res = stack.pop().unwrap() - stack.pop().unwrap();
-                    stack.push(res).unwrap();
+                Opcode::MSTORE => {
+                    let offset = stack.pop()?;
+                    let value = stack.pop()?;
+                    memory.store(offset.as_usize(), value);
                 }
-                Opcode::DIV => {
-                    let res = stack.pop().unwrap() / stack.pop().unwrap();
-                    stack.push(res).unwrap();
+                Opcode::MSTORE8 => {
+                    let offset = stack.pop()?;
+                    let value = stack.pop()?;
+                    memory.store8(offset.as_usize(), value.byte(0));
+                }
+                Opcode::MSIZE => stack.push(Uint::from(memory.size()))?,
+                Opcode::JUMP => {
+                    let destination = stack.pop()?;
+                    pc = jump(destination, &destinations, pc)?;
+                    continue;
+                }
+                Opcode::JUMPI => {
+                    let destination = stack.pop()?;
+                    let condition = stack.pop()?;
+                    if !condition.is_zero() {
+                        pc = jump(destination, &destinations, pc)?;
+                        continue;
+                    }
+                }
+                Opcode::PC => stack.push(Uint::from(pc))?,
+                Opcode::JUMPDEST => {}
+                Opcode::CODECOPY => {
+                    let dest = stack.pop()?;
+                    let offset = stack.pop()?;
+                    let size = stack.pop()?;
+                    let (dest, size) = range(dest, size);
+                    // Past the end of the code are zeros
+                    let start = match offset < Uint::from(code.len()) {
+                        true => offset.as_usize(),
+                        false => code.len(),
+                    };
+                    let mut data = code[start..].iter().take(size).copied().collect::<Vec<_>>();
+                    data.resize(size, 0);
+                    memory.write(dest, &data);
+                }
+                Opcode::RETURN | Opcode::REVERT => {
+                    let offset = stack.pop()?;
+                    let size = stack.pop()?;
+                    let (offset, size) = range(offset, size);
+                    let data = Bytes::from(memory.read(offset, size));
+                    return Ok(match op {
+                        Opcode::RETURN => Outcome::Return(data),
+                        _ => Outcome::Revert(data),
+                    });
                 }
             };
+            pc += 1 + op.immediate_size();
+        }
+
+        Ok(Outcome::Stop(stack.top()))
+    }
+}
+
+/// The offset to jump to from the instruction at `offset`, if it is a JUMPDEST
+fn jump(
+    destination: Uint,
+    destinations: &HashSet<usize>,
+    offset: usize,
+) -> Result<usize, ExecutionError> {
+    Some(destination)
+        .filter(|d| *d <= Uint::from(usize::MAX))
+        .map(|d| d.as_usize())
+        .filter(|d| destinations.contains(d))
+        .ok_or(ExecutionError::InvalidJump {
+            offset,
+            destination,
+        })
+}
+
+/// The `offset` and `size` of a memory access whose gas is charged, `(0, 0)` when empty
+fn range(offset: Uint, size: Uint) -> (usize, usize) {
+    match size.is_zero() {
+        true => (0, 0),
+        false => (offset.as_usize(), size.as_usize()),
+    }
+}
+
+/// Gas an instruction costs on top of its static cost,
+/// depending on its operands and on the memory it expands
+fn dynamic_cost(op: Opcode, stack: &[Uint], memory: usize) -> u64 {
+    // The `n`th operand from the top, missing ones fail the instruction before it matters
+    let operand = |n: usize| stack.iter().rev().nth(n).copied().unwrap_or_default();
+    let expansion = |offset, size| gas::memory_expansion_cost(memory, offset, size);
+    let word = Uint::from(WORD);
+    match op {
+        Opcode::EXP => gas::exp_cost(operand(1)),
+        Opcode::MLOAD | Opcode::MSTORE => expansion(operand(0), word),
+        Opcode::MSTORE8 => expansion(operand(0), Uint::one()),
+        Opcode::RETURN | Opcode::REVERT => expansion(operand(0), operand(1)),
+        Opcode::CODECOPY => {
+            gas::copy_cost(operand(2)).saturating_add(expansion(operand(0), operand(2)))
         }
+        _ => 0,
+    }
+}
+
+/// Use `cost` gas for the instruction at `offset`
+fn charge(gas: &mut Gas, cost: u64, offset: usize) -> Result<(), ExecutionError> {
+    if gas.charge(cost) {
+        Ok(())
+    } else {
+        Err(ExecutionError::OutOfGas {
+            offset,
+            gas_limit: gas.limit,
+        })
+    }
+}
+
+/// Pop the topmost value `a`, then push `f(a)`
+fn unary(stack: &mut Stack<Uint>, f: fn(Uint) -> Uint) -> Result<(), StackError> {
+    let a = stack.pop()?;
+    stack.push(f(a))
+}
+
+/// Pop the two topmost values `a` (top) and `b`, then push `f(a, b)`
+fn binary(stack: &mut Stack<Uint>, f: fn(Uint, Uint) -> Uint) -> Result<(), StackError> {
+    let a = stack.pop()?;
+    let b = stack.pop()?;
+    stack.push(f(a, b))
+}
+
+/// Pop the three topmost values `a` (top), `b` and `c`, then push `f(a, b, c)`
+fn ternary(stack: &mut Stack<Uint>, f: fn(Uint, Uint, Uint) -> Uint) -> Result<(), StackError> {
+    let a = stack.pop()?;
+    let b = stack.pop()?;
+    let c = stack.pop()?;
+    stack.push(f(a, b, c))
+}
+
+/// ADD: `a + b` modulo 2^256
+pub fn add(a: Uint, b: Uint) -> Uint {
+    a.overflowing_add(b).0
+}
+
+/// SUB: `a - b` modulo 2^256
+pub fn sub(a: Uint, b: Uint) -> Uint {
+    a.overflowing_sub(b).0
+}
+
+/// MUL: `a * b` modulo 2^256
+pub fn mul(a: Uint, b: Uint) -> Uint {
+    a.overflowing_mul(b).0
+}
+
+/// DIV: `a / b` rounded towards zero, or 0 when `b` is 0
+pub fn div(a: Uint, b: Uint) -> Uint {
+    a.checked_div(b).unwrap_or_default()
+}
+
+/// MOD: `a % b`, or 0 when `b` is 0
+pub fn modulo(a: Uint, b: Uint) -> Uint {
+    a.checked_rem(b).unwrap_or_default()
+}
+
+/// ADDMOD: `(a + b) % n` without intermediate wrapping, or 0 when `n` is 0
+pub fn addmod(a: Uint, b: Uint, n: Uint) -> Uint {
+    if n.is_zero() {
+        return Uint::zero();
+    }
+    // Safe unwrap here, the remainder is lower than n
+    ((U512::from(a) + U512::from(b)) % U512::from(n))
+        .try_into()
+        .unwrap()
+}
+
+/// MULMOD: `(a * b) % n` without intermediate wrapping, or 0 when `n` is 0
+pub fn mulmod(a: Uint, b: Uint, n: Uint) -> Uint {
+    if n.is_zero() {
+        return Uint::zero();
+    }
+    // Safe unwrap here, the remainder is lower than n
+    (a.full_mul(b) % U512::from(n)).try_into().unwrap()
+}
+
+/// EXP: `a ** b` modulo 2^256
+pub fn exp(a: Uint, b: Uint) -> Uint {
+    a.overflowing_pow(b).0
+}
+
+/// SHL: `value << shift`, or 0 when shifting by 256 bits or more
+pub fn shl(shift: Uint, value: Uint) -> Uint {
+    if shift >= Uint::from(256) {
+        Uint::zero()
+    } else {
+        value << shift.as_usize()
+    }
+}
+
+/// SHR: logical `value >> shift`, or 0 when shifting by 256 bits or more
+pub fn shr(shift: Uint, value: Uint) -> Uint {
+    if shift >= Uint::from(256) {
+        Uint::zero()
+    } else {
+        value >> shift.as_usize()
+    }
+}
+
+/// SAR: arithmetic `value >> shift`, filling with the sign bit
+pub fn sar(shift: Uint, value: Uint) -> Uint {
+    if I256::from_raw(value).is_negative() {
+        !shr(shift, !value)
+    } else {
+        shr(shift, value)
+    }
+}
 
-        stack.top()
+/// SDIV: signed `a / b` rounded towards zero, or 0 when `b` is 0
+///
+/// `-2^255 / -1` overflows back to `-2^255`.
+pub fn sdiv(a: Uint, b: Uint) -> Uint {
+    if b.is_zero() {
+        return Uint::zero();
     }
+    I256::from_raw(a)
+        .overflowing_div(I256::from_raw(b))
+        .0
+        .into_raw()
+}
+
+/// SMOD: signed `a % b` with the sign of `a`, or 0 when `b` is 0
+pub fn smod(a: Uint, b: Uint) -> Uint {
+    if b.is_zero() {
+        return Uint::zero();
+    }
+    I256::from_raw(a)
+        .overflowing_rem(I256::from_raw(b))
+        .0
+        .into_raw()
+}
+
+/// SLT: 1 if `a < b` as signed integers, 0 otherwise
+pub fn slt(a: Uint, b: Uint) -> Uint {
+    Uint::from((I256::from_raw(a) < I256::from_raw(b)) as u8)
+}
+
+/// SGT: 1 if `a > b` as signed integers, 0 otherwise
+pub fn sgt(a: Uint, b: Uint) -> Uint {
+    slt(b, a)
 }
 
 #[cfg(test)]
@@ -92,7 +896,7 @@ mod tests {
         // PUSH 2 PUSH 3 PUSH 4 MUL DIV
         // PUSH 156 ADD
         let b = bytecode(
-            vec![
+            [
                 "7F",                                                               // PUSH32
                 "0000000000000000000000000000000000000000000000000000000000000002", // 2
                 "7F",                                                               // PUSH32
@@ -107,6 +911,532 @@ mod tests {
             ]
             .join(""),
         );
-        assert_eq!(EVM::execute(&b), 162usize.into());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(162usize.into())))
+        );
+    }
+
+    #[test]
+    fn test_evm_push() {
+        // PUSH1 2 PUSH2 0x0100 MUL
+        let b = bytecode("6002610100".to_owned() + "02");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(512usize.into())))
+        );
+        // PUSH0
+        let b = bytecode("5F".to_owned());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::zero())))
+        );
+        // PUSH16
+        let b = bytecode("6F".to_owned() + &"F".repeat(32));
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::from(u128::MAX))))
+        );
+        // Immediates are skipped even when they look like opcodes
+        let b = Bytecode::from(vec![0x61, 0x01, 0xEE, 0x60, 0x01, 0x01]);
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(0x01EFusize.into())))
+        );
+        // PUSH2 with a single byte
+        let b = bytecode("6101".to_owned());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Err(ExecutionError::TruncatedImmediate { offset: 0 })
+        );
+
+        assert_eq!(Opcode::push(0), Some(Opcode::PUSH0));
+        assert_eq!(Opcode::push(1), Some(Opcode::PUSH1));
+        assert_eq!(Opcode::push(32), Some(Opcode::PUSH32));
+        assert_eq!(Opcode::push(33), None);
+        assert_eq!(Opcode::PUSH0.immediate_size(), 0);
+        assert_eq!(Opcode::PUSH20.immediate_size(), 20);
+        assert_eq!(Opcode::ADD.immediate_size(), 0);
+        assert_eq!("6a".parse::<Opcode>().ok(), Some(Opcode::PUSH11));
+        assert_eq!(Opcode::from_mnemonic("push11"), Some(Opcode::PUSH11));
+        assert_eq!(Opcode::from_mnemonic("MULMOD"), Some(Opcode::MULMOD));
+        assert_eq!(Opcode::from_mnemonic("PUSH33"), None);
+        assert_eq!(Opcode::dup(0), None);
+        assert_eq!(Opcode::dup(1), Some(Opcode::DUP1));
+        assert_eq!(Opcode::dup(16), Some(Opcode::DUP16));
+        assert_eq!(Opcode::dup(17), None);
+        assert_eq!(Opcode::swap(3), Some(Opcode::SWAP3));
+        assert_eq!(Opcode::swap(17), None);
+    }
+
+    #[test]
+    fn test_evm_errors() {
+        // Unknown opcode
+        let b = bytecode("7F".to_owned() + &"0".repeat(64) + "EE");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Err(ExecutionError::InvalidOpcode {
+                offset: 33,
+                opcode: 0xEE
+            })
+        );
+        // PUSH32 with a truncated immediate
+        let b = bytecode("7F0102".to_owned());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Err(ExecutionError::TruncatedImmediate { offset: 0 })
+        );
+        // ADD with a single operand
+        let b = bytecode("7F".to_owned() + &"0".repeat(64) + "01");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Err(ExecutionError::Stack(StackError::StackUnderflow))
+        );
+        // Nothing left on the stack
+        let b = bytecode(String::new());
+        let outcome = EVM::default().execute(&b);
+        assert_eq!(outcome, Ok(Outcome::Stop(None)));
+        assert_eq!(
+            outcome.and_then(|o| o.value()),
+            Err(ExecutionError::EmptyStack)
+        );
+    }
+
+    #[test]
+    fn test_evm_memory() {
+        // PUSH1 42 PUSH1 32 MSTORE PUSH1 32 MLOAD
+        let b = bytecode("602A602052602051".to_owned());
+        let mut evm = EVM::default();
+        assert_eq!(evm.execute(&b), Ok(Outcome::Stop(Some(Uint::from(42)))));
+        assert_eq!(evm.memory.size(), 64);
+        // The MSTORE pays for 2 words
+        assert_eq!(evm.gas.used, 3 + 3 + 3 + 6 + 3 + 3);
+
+        // PUSH2 0x1234 PUSH0 MSTORE8 PUSH0 MLOAD MSIZE, MSTORE8 keeps the lowest byte
+        let b = bytecode("6112345F535F5159".to_owned());
+        let mut evm = EVM::default();
+        assert_eq!(evm.execute(&b), Ok(Outcome::Stop(Some(Uint::from(32)))));
+        assert_eq!(
+            evm.stack.as_slice(),
+            [Uint::from(0x34) << 248, Uint::from(32)]
+        );
+
+        // PUSH1 4 PUSH1 8 PUSH0 CODECOPY PUSH1 4 PUSH0 RETURN, zeros past the end of the code
+        let b = bytecode("600460085F3960045FF3".to_owned());
+        let mut evm = EVM::default();
+        assert_eq!(
+            evm.execute(&b),
+            Ok(Outcome::Return(Bytes::from(vec![0x5F, 0xF3, 0, 0])))
+        );
+        assert_eq!(evm.gas.used, 3 + 3 + 2 + (3 + 3 + 3) + 3 + 2);
+
+        // PUSH32 2^256 - 1 MLOAD, far too much memory
+        let b = bytecode(format!("7F{}51", "F".repeat(64)));
+        let mut evm = EVM::new(Fork::Cancun, 1_000_000);
+        assert_eq!(
+            evm.execute(&b),
+            Err(ExecutionError::OutOfGas {
+                offset: 33,
+                gas_limit: 1_000_000
+            })
+        );
+        assert_eq!(evm.gas.used, 1_000_000);
+        // Empty accesses are free wherever they are
+        let b = bytecode(format!("5F7F{}F3", "F".repeat(64)));
+        let mut evm = EVM::default();
+        assert_eq!(evm.execute(&b), Ok(Outcome::Return(Bytes::default())));
+        assert_eq!(evm.gas.used, 2 + 3);
+        assert_eq!(evm.memory.size(), 0);
+    }
+
+    #[test]
+    fn test_evm_jumps() {
+        // PUSH1 cond PUSH1 8 JUMPI PUSH1 0xAA STOP JUMPDEST PUSH1 0xBB
+        let b = |cond: &str| bytecode(format!("60{}60085760AA005B60BB", cond));
+        let mut evm = EVM::default();
+        assert_eq!(
+            evm.execute(&b("01")),
+            Ok(Outcome::Stop(Some(Uint::from(0xBB))))
+        );
+        assert_eq!(evm.stack.len(), 1);
+        assert_eq!(evm.gas.used, 3 + 3 + 10 + 1 + 3);
+        assert_eq!(
+            EVM::default().execute(&b("00")),
+            Ok(Outcome::Stop(Some(Uint::from(0xAA))))
+        );
+
+        // PUSH1 3, then count down to 0 in a loop
+        // JUMPDEST PUSH1 1 SWAP1 SUB DUP1 PUSH1 2 JUMPI
+        let b = bytecode("60035B6001900380600257".to_owned());
+        let mut evm = EVM::default();
+        assert_eq!(evm.execute(&b), Ok(Outcome::Stop(Some(Uint::zero()))));
+        assert_eq!(evm.gas.used, 3 + 3 * (1 + 3 + 3 + 3 + 3 + 3 + 10));
+        // JUMPDEST PUSH0 JUMP, forever until the gas runs out
+        let b = bytecode("5B5F56".to_owned());
+        assert_eq!(
+            EVM::new(Fork::Cancun, 100).execute(&b),
+            Err(ExecutionError::OutOfGas {
+                offset: 1,
+                gas_limit: 100
+            })
+        );
+
+        // PC PC
+        let b = bytecode("5858".to_owned());
+        let mut evm = EVM::default();
+        assert!(evm.execute(&b).is_ok());
+        assert_eq!(evm.stack.as_slice(), [Uint::zero(), Uint::one()]);
+    }
+
+    #[test]
+    fn test_evm_invalid_jumps() {
+        // PUSH1 4 JUMP PUSH1 0x5B, the 0x5B is the immediate of the PUSH1
+        let b = bytecode("600456605B".to_owned());
+        assert_eq!(jump_destinations(&b), HashSet::new());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Err(ExecutionError::InvalidJump {
+                offset: 2,
+                destination: Uint::from(4)
+            })
+        );
+        // PUSH1 1 PUSH1 3 JUMPI, to a PUSH1
+        let b = bytecode("6001600357".to_owned());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Err(ExecutionError::InvalidJump {
+                offset: 4,
+                destination: Uint::from(3)
+            })
+        );
+        // PUSH32 2^256 - 1 JUMP
+        let b = bytecode(format!("7F{}56", "F".repeat(64)));
+        assert_eq!(
+            EVM::default().execute(&b).map_err(|e| e.to_string()),
+            Err(format!(
+                "invalid jump destination 0x{} at offset 33",
+                "f".repeat(64)
+            ))
+        );
+        // Past the end of the code
+        let b = bytecode("60FF56".to_owned());
+        assert!(EVM::default().execute(&b).is_err());
+
+        assert_eq!(
+            jump_destinations(&bytecode("5B605B5B7F5B".to_owned())),
+            HashSet::from([0, 3])
+        );
+    }
+
+    #[test]
+    fn test_evm_halt() {
+        // PUSH1 1 STOP INVALID
+        let b = bytecode("600100EE".to_owned());
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::one())))
+        );
+
+        // PUSH1 42 PUSH0 MSTORE PUSH1 32 PUSH0 RETURN INVALID
+        let b = bytecode("602A5F5260205FF3EE".to_owned());
+        let outcome = EVM::default().execute(&b).unwrap();
+        assert_eq!(outcome.output().len(), 32);
+        assert_eq!(outcome.value(), Ok(Uint::from(42)));
+        // Anything else than a word isn't a result
+        let b = bytecode("602A5F5260015FF3".to_owned());
+        let outcome = EVM::default().execute(&b).unwrap();
+        assert_eq!(outcome.output(), [0]);
+        assert_eq!(outcome.value(), Err(ExecutionError::InvalidReturn(1)));
+
+        // PUSH1 0xAB PUSH0 MSTORE8 PUSH1 1 PUSH0 REVERT
+        let b = bytecode("60AB5F5360015FFD".to_owned());
+        let mut evm = EVM::default();
+        let outcome = evm.execute(&b).unwrap();
+        assert_eq!(outcome, Outcome::Revert(Bytes::from(vec![0xAB])));
+        assert_eq!(
+            outcome.value().map_err(|e| e.to_string()),
+            Err("execution reverted with 0xab".to_owned())
+        );
+        assert_eq!(evm.gas.used, 3 + 2 + 3 + 3 + 3 + 2);
+    }
+
+    #[test]
+    fn test_stack_effect() {
+        assert_eq!(Opcode::PUSH0.stack_effect(), (0, 1));
+        assert_eq!(Opcode::PUSH32.stack_effect(), (0, 1));
+        assert_eq!(Opcode::DUP3.stack_effect(), (3, 4));
+        assert_eq!(Opcode::SWAP16.stack_effect(), (17, 17));
+        assert_eq!(Opcode::ADDMOD.stack_effect(), (3, 1));
+        assert_eq!(Opcode::SAR.stack_effect(), (2, 1));
+        assert_eq!(Opcode::ISZERO.stack_effect(), (1, 1));
+        assert_eq!(Opcode::MSTORE.stack_effect(), (2, 0));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // PUSH1 2 PUSH2 0x0100 MUL PUSH0 INVALID PUSH3 with 2 bytes
+        let b = bytecode("6002610100025FEE620102".to_owned());
+        let instructions = disassemble(&b);
+        assert_eq!(
+            instructions,
+            [
+                Instruction::Op {
+                    offset: 0,
+                    opcode: Opcode::PUSH1,
+                    immediate: vec![0x02]
+                },
+                Instruction::Op {
+                    offset: 2,
+                    opcode: Opcode::PUSH2,
+                    immediate: vec![0x01, 0x00]
+                },
+                Instruction::Op {
+                    offset: 5,
+                    opcode: Opcode::MUL,
+                    immediate: vec![]
+                },
+                Instruction::Op {
+                    offset: 6,
+                    opcode: Opcode::PUSH0,
+                    immediate: vec![]
+                },
+                Instruction::Invalid {
+                    offset: 7,
+                    byte: 0xEE
+                },
+                Instruction::Truncated {
+                    offset: 8,
+                    opcode: Opcode::PUSH3,
+                    immediate: vec![0x01, 0x02]
+                },
+            ]
+        );
+        let lines: Vec<_> = instructions.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                "0x0000: PUSH1 0x02",
+                "0x0002: PUSH2 0x0100",
+                "0x0005: MUL",
+                "0x0006: PUSH0",
+                "0x0007: INVALID 0xee",
+                "0x0008: PUSH3 0x0102 ; truncated, 2 of 3 bytes",
+            ]
+        );
+        assert_eq!(disassemble(&bytecode(String::new())), []);
+        assert_eq!(
+            disassemble(&bytecode("7F".to_owned()))[0].to_string(),
+            "0x0000: PUSH32 0x ; truncated, 0 of 32 bytes"
+        );
+    }
+
+    #[test]
+    fn test_evm_gas() {
+        // PUSH1 2 PUSH1 3 ADD
+        let b = bytecode("6002600301".to_owned());
+        let mut evm = EVM::default();
+        assert_eq!(evm.execute(&b), Ok(Outcome::Stop(Some(5usize.into()))));
+        assert_eq!(evm.gas.used, 9);
+        // PUSH2 0x0100 PUSH1 2 EXP, the exponent takes 2 bytes
+        let b = bytecode("61010060020A".to_owned());
+        let mut evm = EVM::default();
+        assert_eq!(evm.execute(&b), Ok(Outcome::Stop(Some(Uint::zero()))));
+        assert_eq!(evm.gas.used, 3 + 3 + 10 + 2 * 50);
+        // PUSH0 costs 2 from Shanghai and doesn't exist before
+        let b = bytecode("5F".to_owned());
+        let mut evm = EVM::new(Fork::Shanghai, 2);
+        assert_eq!(evm.execute(&b), Ok(Outcome::Stop(Some(Uint::zero()))));
+        assert_eq!(evm.gas.used, 2);
+        assert_eq!(
+            EVM::new(Fork::Berlin, 2).execute(&b),
+            Err(ExecutionError::InvalidOpcode {
+                offset: 0,
+                opcode: 0x5F
+            })
+        );
+        // Running out of gas uses all of it
+        let b = bytecode("6002600301".to_owned());
+        let mut evm = EVM::new(Fork::Cancun, 8);
+        assert_eq!(
+            evm.execute(&b),
+            Err(ExecutionError::OutOfGas {
+                offset: 4,
+                gas_limit: 8
+            })
+        );
+        assert_eq!(evm.gas.used, 8);
+        // Including on the dynamic cost of EXP
+        let b = bytecode("61010060020A".to_owned());
+        assert_eq!(
+            EVM::new(Fork::Cancun, 100).execute(&b),
+            Err(ExecutionError::OutOfGas {
+                offset: 5,
+                gas_limit: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_wrapping_arithmetic() {
+        let max = Uint::MAX;
+        let zero = Uint::zero();
+        let one = Uint::one();
+        let two = Uint::from(2);
+        let half = one << 255;
+
+        // ADD wraps around 2^256
+        assert_eq!(add(max, one), zero);
+        assert_eq!(add(max, max), max - one);
+        assert_eq!(add(half, half), zero);
+        assert_eq!(add(zero, zero), zero);
+
+        // SUB wraps below zero
+        assert_eq!(sub(zero, one), max);
+        assert_eq!(sub(one, two), max);
+        assert_eq!(sub(zero, max), one);
+        assert_eq!(sub(max, max), zero);
+
+        // MUL keeps the low 256 bits
+        assert_eq!(mul(half, two), zero);
+        assert_eq!(mul(max, max), one);
+        assert_eq!(mul(max, two), max - one);
+        assert_eq!(mul(max, zero), zero);
+
+        // DIV truncates and returns 0 on division by zero
+        assert_eq!(div(one, zero), zero);
+        assert_eq!(div(zero, zero), zero);
+        assert_eq!(div(max, zero), zero);
+        assert_eq!(div(max, one), max);
+        assert_eq!(div(max, max), one);
+        assert_eq!(div(Uint::from(7), two), Uint::from(3));
+        assert_eq!(div(one, two), zero);
+    }
+
+    #[test]
+    fn test_bitwise_arithmetic() {
+        let max = Uint::MAX;
+        let zero = Uint::zero();
+        let one = Uint::one();
+        let two = Uint::from(2);
+        let int = |i: i64| I256::from(i).into_raw();
+
+        // ADDMOD and MULMOD don't wrap before the modulo
+        assert_eq!(addmod(max, one, max), one);
+        assert_eq!(addmod(max, max, Uint::from(7)), (max % 7) * 2 % 7);
+        assert_eq!(addmod(one, two, zero), zero);
+        assert_eq!(mulmod(max, max, max - one), one);
+        assert_eq!(mulmod(two, Uint::from(5), Uint::from(3)), one);
+        assert_eq!(mulmod(one, two, zero), zero);
+
+        // MOD returns 0 on modulo zero
+        assert_eq!(modulo(Uint::from(7), Uint::from(3)), one);
+        assert_eq!(modulo(Uint::from(7), zero), zero);
+        assert_eq!(modulo(max, max), zero);
+
+        // EXP wraps around 2^256
+        assert_eq!(exp(two, Uint::from(255)), one << 255);
+        assert_eq!(exp(two, Uint::from(256)), zero);
+        assert_eq!(exp(zero, zero), one);
+        assert_eq!(exp(max, two), one);
+
+        // Shifts of 256 bits or more clear everything
+        assert_eq!(shl(Uint::from(255), one), one << 255);
+        assert_eq!(shl(Uint::from(256), one), zero);
+        assert_eq!(shl(max, max), zero);
+        assert_eq!(shr(Uint::from(255), max), one);
+        assert_eq!(shr(Uint::from(256), max), zero);
+
+        // SAR keeps the sign
+        assert_eq!(sar(one, int(-4)), int(-2));
+        assert_eq!(sar(one, int(-1)), int(-1));
+        assert_eq!(sar(Uint::from(256), int(-4)), max);
+        assert_eq!(sar(Uint::from(256), int(4)), zero);
+        assert_eq!(sar(one, int(4)), two);
+    }
+
+    #[test]
+    fn test_signed_arithmetic() {
+        let int = |i: i64| I256::from(i).into_raw();
+        let zero = Uint::zero();
+        let min = I256::MIN.into_raw();
+        let max = I256::MAX.into_raw();
+
+        // SDIV rounds towards zero
+        assert_eq!(sdiv(int(-7), int(2)), int(-3));
+        assert_eq!(sdiv(int(7), int(-2)), int(-3));
+        assert_eq!(sdiv(int(-7), int(-2)), int(3));
+        assert_eq!(sdiv(int(-7), zero), zero);
+        assert_eq!(sdiv(min, int(-1)), min);
+        assert_eq!(sdiv(max, int(-1)), (-I256::MAX).into_raw());
+
+        // SMOD takes the sign of the dividend
+        assert_eq!(smod(int(-7), int(2)), int(-1));
+        assert_eq!(smod(int(7), int(-2)), int(1));
+        assert_eq!(smod(int(-8), int(3)), int(-2));
+        assert_eq!(smod(int(-7), zero), zero);
+        assert_eq!(smod(min, int(-1)), zero);
+
+        // SLT compares as two's complement
+        assert_eq!(slt(int(-1), int(0)), Uint::one());
+        assert_eq!(slt(int(0), int(-1)), zero);
+        assert_eq!(slt(min, max), Uint::one());
+        assert_eq!(slt(int(3), int(3)), zero);
+        assert_eq!(sgt(int(0), int(-1)), Uint::one());
+        assert_eq!(sgt(min, max), zero);
+        assert_eq!(sgt(int(3), int(3)), zero);
+    }
+
+    #[test]
+    fn test_evm_wrapping() {
+        let push = |u: Uint| format!("7F{:064X}", u);
+        // 0 - 1
+        let b = bytecode(push(1.into()) + &push(0.into()) + "03");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::MAX)))
+        );
+        // 2**255 * 2
+        let b = bytecode(push(2.into()) + &push(Uint::one() << 255) + "02");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::zero())))
+        );
+        // 1 / 0
+        let b = bytecode(push(0.into()) + &push(1.into()) + "04");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::zero())))
+        );
+        // MAX + 1
+        let b = bytecode(push(1.into()) + &push(Uint::MAX) + "01");
+        assert_eq!(
+            EVM::default().execute(&b),
+            Ok(Outcome::Stop(Some(Uint::zero())))
+        );
+    }
+
+    #[test]
+    fn test_evm_comparisons() {
+        let run = |code: String| {
+            EVM::default()
+                .execute(&bytecode(code))
+                .and_then(|o| o.value())
+        };
+        let one = Ok(Uint::one());
+        let zero = Ok(Uint::zero());
+        // PUSH1 2 PUSH1 1 op, comparing 1 to 2
+        assert_eq!(run("6002600110".to_owned()), one);
+        assert_eq!(run("6002600111".to_owned()), zero);
+        assert_eq!(run("6002600114".to_owned()), zero);
+        assert_eq!(run("6001600114".to_owned()), one);
+        // PUSH0 ISZERO, PUSH1 2 ISZERO
+        assert_eq!(run("5F15".to_owned()), one);
+        assert_eq!(run("600215".to_owned()), zero);
+        // PUSH1 1 PUSH32 -1 op, comparing -1 to 1
+        let b = |op: &str| format!("60017F{}{}", "F".repeat(64), op);
+        assert_eq!(run(b("10")), zero);
+        assert_eq!(run(b("11")), one);
+        assert_eq!(run(b("12")), one);
+        assert_eq!(run(b("13")), zero);
     }
 }
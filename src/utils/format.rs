@@ -0,0 +1,144 @@
+use std::{fmt::Display, str::FromStr};
+
+use ethers_core::{abi::Uint, types::I256};
+
+/// How a result is written out
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ResultFormat {
+    /// uint256 in decimal, e.g. `255`
+    #[default]
+    Decimal,
+    /// e.g. `0xff`
+    Hex,
+    /// e.g. `0b11111111`
+    Binary,
+    /// The 32-byte ABI word, e.g. `0x00...00ff`
+    Word,
+    /// int256 in decimal, e.g. `-1`
+    Signed,
+    /// Amount of wei in ether, e.g. `1.5 ether`
+    Ether,
+    /// Amount of wei in gwei, e.g. `20 gwei`
+    Gwei,
+}
+
+impl ResultFormat {
+    /// Write `value` in this format
+    pub fn format(&self, value: Uint) -> String {
+        match self {
+            ResultFormat::Decimal => value.to_string(),
+            ResultFormat::Hex => format!("{:#x}", value),
+            ResultFormat::Binary => {
+                let bits = (0..value.bits().max(1))
+                    .rev()
+                    .map(|i| if value.bit(i) { '1' } else { '0' })
+                    .collect::<String>();
+                format!("0b{}", bits)
+            }
+            ResultFormat::Word => {
+                let mut word = [0u8; 32];
+                value.to_big_endian(&mut word);
+                let hex = word
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                format!("0x{}", hex)
+            }
+            ResultFormat::Signed => I256::from_raw(value).to_string(),
+            ResultFormat::Ether => format!("{} ether", decimals(value, 18)),
+            ResultFormat::Gwei => format!("{} gwei", decimals(value, 9)),
+        }
+    }
+}
+
+/// Write `value` divided by 10^`decimals`, without trailing zeros
+fn decimals(value: Uint, decimals: usize) -> String {
+    let unit = Uint::exp10(decimals);
+    let fraction = (value % unit).to_string();
+    let fraction = format!("{:0>width$}", fraction, width = decimals);
+    let fraction = fraction.trim_end_matches('0');
+    match fraction {
+        "" => (value / unit).to_string(),
+        _ => format!("{}.{}", value / unit, fraction),
+    }
+}
+
+impl Display for ResultFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultFormat::Decimal => write!(f, "decimal"),
+            ResultFormat::Hex => write!(f, "hex"),
+            ResultFormat::Binary => write!(f, "binary"),
+            ResultFormat::Word => write!(f, "word"),
+            ResultFormat::Signed => write!(f, "signed"),
+            ResultFormat::Ether => write!(f, "ether"),
+            ResultFormat::Gwei => write!(f, "gwei"),
+        }
+    }
+}
+
+impl FromStr for ResultFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "decimal" | "dec" => Ok(ResultFormat::Decimal),
+            "hex" => Ok(ResultFormat::Hex),
+            "binary" | "bin" => Ok(ResultFormat::Binary),
+            "word" | "abi" => Ok(ResultFormat::Word),
+            "signed" | "int256" => Ok(ResultFormat::Signed),
+            "ether" => Ok(ResultFormat::Ether),
+            "gwei" => Ok(ResultFormat::Gwei),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let value = Uint::from(255);
+        assert_eq!(ResultFormat::Decimal.format(value), "255");
+        assert_eq!(ResultFormat::Hex.format(value), "0xff");
+        assert_eq!(ResultFormat::Binary.format(value), "0b11111111");
+        assert_eq!(ResultFormat::Binary.format(Uint::zero()), "0b0");
+        assert_eq!(
+            ResultFormat::Word.format(value),
+            format!("0x{}ff", "0".repeat(62))
+        );
+        assert_eq!(ResultFormat::Signed.format(value), "255");
+        assert_eq!(ResultFormat::Signed.format(Uint::MAX), "-1");
+        assert_eq!(
+            ResultFormat::Hex.format(Uint::MAX),
+            format!("0x{}", "f".repeat(64))
+        );
+
+        let ether = Uint::exp10(18);
+        assert_eq!(ResultFormat::Ether.format(ether * 3 / 2), "1.5 ether");
+        assert_eq!(ResultFormat::Ether.format(ether * 2), "2 ether");
+        assert_eq!(
+            ResultFormat::Ether.format(Uint::one()),
+            "0.000000000000000001 ether"
+        );
+        assert_eq!(ResultFormat::Ether.format(Uint::zero()), "0 ether");
+        assert_eq!(
+            ResultFormat::Gwei.format(Uint::from(20_000_000_000u64)),
+            "20 gwei"
+        );
+        assert_eq!(
+            ResultFormat::Gwei.format(Uint::from(1_500)),
+            "0.0000015 gwei"
+        );
+
+        assert_eq!("HEX".parse(), Ok(ResultFormat::Hex));
+        assert_eq!("abi".parse(), Ok(ResultFormat::Word));
+        assert_eq!(
+            ResultFormat::Gwei.to_string().parse(),
+            Ok(ResultFormat::Gwei)
+        );
+        assert!("octal".parse::<ResultFormat>().is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::{fmt::Display, str::FromStr};
+
+use ethers_core::abi::Uint;
+
+use crate::utils::{evm::Opcode, memory::WORD};
+
+/// The block gas limit, used when no gas limit is given
+pub const DEFAULT_GAS_LIMIT: u64 = 30_000_000;
+
+// Gas tiers from the yellow paper
+pub const ZERO: u64 = 0;
+pub const BASE: u64 = 2;
+pub const VERY_LOW: u64 = 3;
+pub const LOW: u64 = 5;
+pub const MID: u64 = 8;
+pub const HIGH: u64 = 10;
+pub const EXP: u64 = 10;
+/// JUMPDEST has a tier of its own
+pub const JUMPDEST: u64 = 1;
+/// EXP cost per byte of exponent
+pub const EXP_BYTE: u64 = 50;
+/// Memory cost per word, on top of the quadratic cost
+pub const MEMORY: u64 = 3;
+/// CODECOPY cost per word copied
+pub const COPY: u64 = 3;
+/// Largest memory size the gas accounting handles, any access past it runs out of gas
+pub const MAX_MEMORY: usize = u32::MAX as usize;
+
+/// Hard forks with their own instruction set and gas schedule
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Default)]
+pub enum Fork {
+    Berlin,
+    /// Adds PUSH0 (EIP-3855)
+    Shanghai,
+    #[default]
+    Cancun,
+}
+
+impl Fork {
+    /// Whether PUSH0 is available
+    pub fn has_push0(&self) -> bool {
+        *self >= Fork::Shanghai
+    }
+
+    /// Static gas cost of an opcode,
+    /// or `None` if the opcode isn't available in this fork
+    pub fn static_cost(&self, opcode: Opcode) -> Option<u64> {
+        let cost = match opcode {
+            Opcode::PUSH0 if !self.has_push0() => return None,
+            Opcode::STOP | Opcode::RETURN | Opcode::REVERT => ZERO,
+            Opcode::JUMPDEST => JUMPDEST,
+            Opcode::PUSH0 | Opcode::POP | Opcode::PC | Opcode::MSIZE => BASE,
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::LT
+            | Opcode::GT
+            | Opcode::SLT
+            | Opcode::SGT
+            | Opcode::EQ
+            | Opcode::ISZERO
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::NOT
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::SAR
+            | Opcode::CODECOPY
+            | Opcode::MLOAD
+            | Opcode::MSTORE
+            | Opcode::MSTORE8
+            | Opcode::DUP1
+            | Opcode::DUP2
+            | Opcode::DUP3
+            | Opcode::DUP4
+            | Opcode::DUP5
+            | Opcode::DUP6
+            | Opcode::DUP7
+            | Opcode::DUP8
+            | Opcode::DUP9
+            | Opcode::DUP10
+            | Opcode::DUP11
+            | Opcode::DUP12
+            | Opcode::DUP13
+            | Opcode::DUP14
+            | Opcode::DUP15
+            | Opcode::DUP16
+            | Opcode::SWAP1
+            | Opcode::SWAP2
+            | Opcode::SWAP3
+            | Opcode::SWAP4
+            | Opcode::SWAP5
+            | Opcode::SWAP6
+            | Opcode::SWAP7
+            | Opcode::SWAP8
+            | Opcode::SWAP9
+            | Opcode::SWAP10
+            | Opcode::SWAP11
+            | Opcode::SWAP12
+            | Opcode::SWAP13
+            | Opcode::SWAP14
+            | Opcode::SWAP15
+            | Opcode::SWAP16
+            | Opcode::PUSH1
+            | Opcode::PUSH2
+            | Opcode::PUSH3
+            | Opcode::PUSH4
+            | Opcode::PUSH5
+            | Opcode::PUSH6
+            | Opcode::PUSH7
+            | Opcode::PUSH8
+            | Opcode::PUSH9
+            | Opcode::PUSH10
+            | Opcode::PUSH11
+            | Opcode::PUSH12
+            | Opcode::PUSH13
+            | Opcode::PUSH14
+            | Opcode::PUSH15
+            | Opcode::PUSH16
+            | Opcode::PUSH17
+            | Opcode::PUSH18
+            | Opcode::PUSH19
+            | Opcode::PUSH20
+            | Opcode::PUSH21
+            | Opcode::PUSH22
+            | Opcode::PUSH23
+            | Opcode::PUSH24
+            | Opcode::PUSH25
+            | Opcode::PUSH26
+            | Opcode::PUSH27
+            | Opcode::PUSH28
+            | Opcode::PUSH29
+            | Opcode::PUSH30
+            | Opcode::PUSH31
+            | Opcode::PUSH32 => VERY_LOW,
+            Opcode::MUL | Opcode::DIV | Opcode::SDIV | Opcode::MOD | Opcode::SMOD => LOW,
+            Opcode::ADDMOD | Opcode::MULMOD | Opcode::JUMP => MID,
+            Opcode::JUMPI => HIGH,
+            Opcode::EXP => EXP,
+        };
+        Some(cost)
+    }
+}
+
+impl Display for Fork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fork::Berlin => write!(f, "berlin"),
+            Fork::Shanghai => write!(f, "shanghai"),
+            Fork::Cancun => write!(f, "cancun"),
+        }
+    }
+}
+
+impl FromStr for Fork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "berlin" => Ok(Fork::Berlin),
+            "shanghai" => Ok(Fork::Shanghai),
+            "cancun" => Ok(Fork::Cancun),
+            _ => Err(format!("unknown fork: {}", s)),
+        }
+    }
+}
+
+/// Dynamic gas cost of EXP, paid on top of its static cost
+pub fn exp_cost(exponent: Uint) -> u64 {
+    EXP_BYTE * exponent.bits().div_ceil(8) as u64
+}
+
+/// Total cost of a memory of `size` bytes
+pub fn memory_cost(size: usize) -> u64 {
+    let words = size.div_ceil(WORD) as u64;
+    MEMORY * words + words * words / 512
+}
+
+/// Dynamic gas cost of accessing `size` bytes from `offset` in a memory of `current` bytes,
+/// paying for the words it expands the memory by
+///
+/// `u64::MAX` when the access goes past [`MAX_MEMORY`].
+pub fn memory_expansion_cost(current: usize, offset: Uint, size: Uint) -> u64 {
+    if size.is_zero() {
+        return 0;
+    }
+    match offset.checked_add(size) {
+        Some(end) if end <= Uint::from(MAX_MEMORY) => {
+            memory_cost(end.as_usize()).saturating_sub(memory_cost(current))
+        }
+        _ => u64::MAX,
+    }
+}
+
+/// Dynamic gas cost of copying `size` bytes
+pub fn copy_cost(size: Uint) -> u64 {
+    match size <= Uint::from(MAX_MEMORY) {
+        true => COPY * size.as_usize().div_ceil(WORD) as u64,
+        false => u64::MAX,
+    }
+}
+
+/// Gas accounting of an execution
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Gas {
+    pub limit: u64,
+    pub used: u64,
+}
+
+impl Gas {
+    pub fn new(limit: u64) -> Self {
+        Gas { limit, used: 0 }
+    }
+
+    /// Gas left to use
+    pub fn remaining(&self) -> u64 {
+        self.limit - self.used
+    }
+
+    /// Use `cost` gas, or use up the remaining gas and return false
+    /// if there isn't enough of it
+    pub fn charge(&mut self, cost: u64) -> bool {
+        if cost > self.remaining() {
+            self.used = self.limit;
+            false
+        } else {
+            self.used += cost;
+            true
+        }
+    }
+}
+
+impl Default for Gas {
+    fn default() -> Self {
+        Gas::new(DEFAULT_GAS_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gas() {
+        assert_eq!(Fork::Cancun.static_cost(Opcode::PUSH0), Some(2));
+        assert_eq!(Fork::Shanghai.static_cost(Opcode::PUSH0), Some(2));
+        assert_eq!(Fork::Berlin.static_cost(Opcode::PUSH0), None);
+        assert_eq!(Fork::Berlin.static_cost(Opcode::PUSH32), Some(3));
+        assert_eq!(Fork::Berlin.static_cost(Opcode::MULMOD), Some(8));
+        assert_eq!(Fork::Cancun.static_cost(Opcode::SDIV), Some(5));
+        assert_eq!(Fork::Cancun.static_cost(Opcode::MSTORE), Some(3));
+        assert_eq!(Fork::Cancun.static_cost(Opcode::RETURN), Some(0));
+        assert_eq!(Fork::Berlin.static_cost(Opcode::JUMPI), Some(10));
+        assert_eq!(Fork::Berlin.static_cost(Opcode::JUMPDEST), Some(1));
+
+        assert_eq!(exp_cost(Uint::zero()), 0);
+        assert_eq!(exp_cost(Uint::from(255)), 50);
+        assert_eq!(exp_cost(Uint::from(256)), 100);
+        assert_eq!(exp_cost(Uint::MAX), 1600);
+
+        assert_eq!(memory_cost(0), 0);
+        assert_eq!(memory_cost(1), 3);
+        assert_eq!(memory_cost(32), 3);
+        assert_eq!(memory_cost(32 * 1024), 3 * 1024 + 2048);
+        let word = Uint::from(32);
+        assert_eq!(memory_expansion_cost(0, Uint::zero(), word), 3);
+        assert_eq!(memory_expansion_cost(32, Uint::zero(), word), 0);
+        assert_eq!(memory_expansion_cost(32, Uint::one(), word), 3);
+        assert_eq!(memory_expansion_cost(0, Uint::MAX, Uint::zero()), 0);
+        assert_eq!(memory_expansion_cost(0, Uint::MAX, word), u64::MAX);
+        assert_eq!(copy_cost(Uint::zero()), 0);
+        assert_eq!(copy_cost(Uint::from(33)), 6);
+        assert_eq!(copy_cost(Uint::MAX), u64::MAX);
+
+        let mut gas = Gas::new(10);
+        assert!(gas.charge(3));
+        assert!(gas.charge(7));
+        assert_eq!(gas.remaining(), 0);
+        assert!(!gas.charge(1));
+        let mut gas = Gas::new(10);
+        assert!(!gas.charge(11));
+        assert_eq!(gas.used, 10);
+
+        assert_eq!("Berlin".parse(), Ok(Fork::Berlin));
+        assert_eq!(Fork::Shanghai.to_string().parse(), Ok(Fork::Shanghai));
+        assert!("london".parse::<Fork>().is_err());
+    }
+}
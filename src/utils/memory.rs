@@ -0,0 +1,100 @@
+use ethers_core::abi::Uint;
+
+/// Size in bytes of a memory word, the memory expands a word at a time
+pub const WORD: usize = 32;
+
+/// Byte-addressable memory of the EVM, zeroed until written
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Memory(Vec<u8>);
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size of the memory in bytes, a multiple of 32
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Expand the memory to cover `size` bytes from `offset`,
+    /// nothing when `size` is 0
+    pub fn expand(&mut self, offset: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let end = (offset + size).next_multiple_of(WORD);
+        if end > self.0.len() {
+            self.0.resize(end, 0);
+        }
+    }
+
+    /// Read the word at `offset` (MLOAD)
+    pub fn load(&mut self, offset: usize) -> Uint {
+        self.expand(offset, WORD);
+        Uint::from_big_endian(&self.0[offset..offset + WORD])
+    }
+
+    /// Write `value` as a word at `offset` (MSTORE)
+    pub fn store(&mut self, offset: usize, value: Uint) {
+        self.expand(offset, WORD);
+        value.to_big_endian(&mut self.0[offset..offset + WORD]);
+    }
+
+    /// Write a single byte at `offset` (MSTORE8)
+    pub fn store8(&mut self, offset: usize, byte: u8) {
+        self.expand(offset, 1);
+        self.0[offset] = byte;
+    }
+
+    /// Read `size` bytes from `offset`
+    pub fn read(&mut self, offset: usize, size: usize) -> Vec<u8> {
+        if size == 0 {
+            return Vec::new();
+        }
+        self.expand(offset, size);
+        self.0[offset..offset + size].to_vec()
+    }
+
+    /// Write `data` from `offset`
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.expand(offset, data.len());
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.size(), 0);
+        // Reading expands too
+        assert_eq!(memory.load(0), Uint::zero());
+        assert_eq!(memory.size(), 32);
+        // A word across two words
+        memory.store(1, Uint::from(0xABCD));
+        assert_eq!(memory.size(), 64);
+        assert_eq!(memory.read(31, 2), [0xAB, 0xCD]);
+        assert_eq!(memory.load(1), Uint::from(0xABCD));
+        assert_eq!(memory.load(0), Uint::from(0xAB));
+
+        memory.store8(64, 0xFF);
+        assert_eq!(memory.size(), 96);
+        assert_eq!(memory.read(63, 3), [0, 0xFF, 0]);
+        memory.write(95, &[1, 2]);
+        assert_eq!(memory.size(), 128);
+        assert_eq!(memory.read(95, 2), [1, 2]);
+
+        // Empty accesses don't expand, wherever they are
+        assert!(memory.read(1000, 0).is_empty());
+        memory.write(1000, &[]);
+        memory.expand(1000, 0);
+        assert_eq!(memory.size(), 128);
+    }
+}
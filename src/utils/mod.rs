@@ -1,4 +1,10 @@
+pub mod diagnostics;
 pub mod errors;
 pub mod evm;
+pub mod format;
+pub mod gas;
+pub mod memory;
+pub mod span;
 pub mod stack;
 pub mod tokens;
+pub mod tracer;
@@ -3,7 +3,7 @@ use thiserror::Error;
 
 pub const MAX_FRAMES: usize = 1024;
 
-#[derive(Error, Debug, PartialEq)]
+#[derive(Error, Debug, PartialEq, Eq)]
 pub enum StackError {
     #[error("stack overflow")]
     StackOverflow,
@@ -54,8 +54,42 @@ where
         }
     }
 
-    pub fn top(&self) -> T {
-        self.arr[self.ptr - 1]
+    pub fn len(&self) -> usize {
+        self.ptr
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ptr == 0
+    }
+
+    pub fn top(&self) -> Option<T> {
+        self.ptr.checked_sub(1).map(|i| self.arr[i])
+    }
+
+    /// Push a copy of the `n`th value from the top (DUPn)
+    pub fn dup(&mut self, n: usize) -> Result<(), StackError> {
+        let i = self
+            .ptr
+            .checked_sub(n)
+            .filter(|_| n > 0)
+            .ok_or(StackError::StackUnderflow)?;
+        self.push(self.arr[i])
+    }
+
+    /// Exchange the top value with the `n + 1`th one (SWAPn)
+    pub fn swap(&mut self, n: usize) -> Result<(), StackError> {
+        let i = self
+            .ptr
+            .checked_sub(n + 1)
+            .filter(|_| n > 0)
+            .ok_or(StackError::StackUnderflow)?;
+        self.arr.swap(i, self.ptr - 1);
+        Ok(())
+    }
+
+    /// Values on the stack, from bottom to top
+    pub fn as_slice(&self) -> &[T] {
+        &self.arr[..self.ptr]
     }
 }
 
@@ -81,6 +115,7 @@ mod tests {
         let mut stack = Stack::new();
         // Check pointer start
         assert_eq!(stack.ptr, 0);
+        assert_eq!(stack.top(), None);
         // Check pop stack underflow protection
         let res = stack.pop();
         assert!(res.is_err());
@@ -90,7 +125,8 @@ mod tests {
         let data = 2usize;
         stack.push(data).expect("Push shouldn't fail!");
         assert_eq!(stack.ptr, 1);
-        assert_eq!(stack.top(), data);
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.top(), Some(data));
         // Check push stack overflow protection
         while stack.push(data).is_ok() {}
         assert_eq!(stack.ptr, MAX_FRAMES);
@@ -101,5 +137,18 @@ mod tests {
         // Check pop
         stack.pop().expect("Pop shouldn't fail!");
         assert_eq!(stack.ptr, MAX_FRAMES - 1); // 1022
+
+        // Check dup and swap
+        let mut stack = Stack::new();
+        for i in 1..=3usize {
+            stack.push(i).unwrap();
+        }
+        stack.dup(3).expect("Dup shouldn't fail!");
+        assert_eq!(stack.as_slice(), [1, 2, 3, 1]);
+        stack.swap(2).expect("Swap shouldn't fail!");
+        assert_eq!(stack.as_slice(), [1, 1, 3, 2]);
+        assert_eq!(stack.dup(5), Err(StackError::StackUnderflow));
+        assert_eq!(stack.swap(4), Err(StackError::StackUnderflow));
+        assert_eq!(stack.dup(0), Err(StackError::StackUnderflow));
     }
 }
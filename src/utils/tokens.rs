@@ -17,26 +17,130 @@ pub enum Token {
     Mul,
     /// Division
     Div,
+    /// Modulo
+    Mod,
+    /// Exponentiation
+    Pow,
+    /// Bitwise and
+    BitAnd,
+    /// Bitwise or
+    BitOr,
+    /// Bitwise exclusive or
+    BitXor,
+    /// Bitwise not
+    BitNot,
+    /// Left shift
+    Shl,
+    /// Right shift
+    Shr,
+    /// Less than
+    Lt,
+    /// Greater than
+    Gt,
+    /// Less than or equal
+    Le,
+    /// Greater than or equal
+    Ge,
+    /// Equality
+    Eq,
+    /// Inequality
+    Ne,
+    /// Logical and
+    And,
+    /// Logical or
+    Or,
+    /// Logical not
+    Not,
+    /// Start of the branches of a conditional, `c ? a : b`
+    Question,
+    /// Between the branches of a conditional
+    Colon,
+    /// Negation (unary minus)
+    Neg,
     /// Number
     Num(U256),
+    /// Name of a variable or function
+    Ident(String),
+    /// Argument separator
+    Comma,
+    /// The `let` keyword
+    Let,
+    /// The `if` keyword
+    If,
+    /// The `then` keyword
+    Then,
+    /// The `else` keyword
+    Else,
+    /// Binding of a name to a value
+    Assign,
+    /// End of a binding
+    Semicolon,
     /// A Space
     Whitespace,
 }
 
 impl Token {
-    /// Public function that returns an operator info
+    /// Precedence, associativity and arity of the token, if it is an operator
     pub fn operator(&self) -> Option<Operator> {
         match self {
-            &Token::Add | &Token::Sub => Some(Operator {
+            &Token::Or => Some(Operator {
                 precedence: 0,
                 associativity: Associativity::Left,
                 arity: 2,
             }),
-            &Token::Mul | &Token::Div => Some(Operator {
+            &Token::And => Some(Operator {
                 precedence: 1,
                 associativity: Associativity::Left,
                 arity: 2,
             }),
+            // Comparisons bind looser than bitwise operations, e.g. `x & 1 == 1`
+            &Token::Lt | &Token::Gt | &Token::Le | &Token::Ge | &Token::Eq | &Token::Ne => {
+                Some(Operator {
+                    precedence: 2,
+                    associativity: Associativity::Left,
+                    arity: 2,
+                })
+            }
+            &Token::BitOr => Some(Operator {
+                precedence: 3,
+                associativity: Associativity::Left,
+                arity: 2,
+            }),
+            &Token::BitXor => Some(Operator {
+                precedence: 4,
+                associativity: Associativity::Left,
+                arity: 2,
+            }),
+            &Token::BitAnd => Some(Operator {
+                precedence: 5,
+                associativity: Associativity::Left,
+                arity: 2,
+            }),
+            &Token::Shl | &Token::Shr => Some(Operator {
+                precedence: 6,
+                associativity: Associativity::Left,
+                arity: 2,
+            }),
+            &Token::Add | &Token::Sub => Some(Operator {
+                precedence: 7,
+                associativity: Associativity::Left,
+                arity: 2,
+            }),
+            &Token::Mul | &Token::Div | &Token::Mod => Some(Operator {
+                precedence: 8,
+                associativity: Associativity::Left,
+                arity: 2,
+            }),
+            &Token::Neg | &Token::BitNot | &Token::Not => Some(Operator {
+                precedence: 9,
+                associativity: Associativity::Right,
+                arity: 1,
+            }),
+            &Token::Pow => Some(Operator {
+                precedence: 10,
+                associativity: Associativity::Right,
+                arity: 2,
+            }),
             _ => None,
         }
     }
@@ -51,7 +155,35 @@ impl Display for Token {
             Token::Sub => String::from("-"),
             Token::Mul => String::from("*"),
             Token::Div => String::from("/"),
+            Token::Mod => String::from("%"),
+            Token::Pow => String::from("**"),
+            Token::BitAnd => String::from("&"),
+            Token::BitOr => String::from("|"),
+            Token::BitXor => String::from("^"),
+            Token::BitNot => String::from("~"),
+            Token::Shl => String::from("<<"),
+            Token::Shr => String::from(">>"),
+            Token::Lt => String::from("<"),
+            Token::Gt => String::from(">"),
+            Token::Le => String::from("<="),
+            Token::Ge => String::from(">="),
+            Token::Eq => String::from("=="),
+            Token::Ne => String::from("!="),
+            Token::And => String::from("&&"),
+            Token::Or => String::from("||"),
+            Token::Not => String::from("!"),
+            Token::Question => String::from("?"),
+            Token::Colon => String::from(":"),
+            Token::Neg => String::from("-"),
             Token::Num(n) => n.to_string(),
+            Token::Ident(name) => name.clone(),
+            Token::Comma => String::from(","),
+            Token::Let => String::from("let"),
+            Token::If => String::from("if"),
+            Token::Then => String::from("then"),
+            Token::Else => String::from("else"),
+            Token::Assign => String::from("="),
+            Token::Semicolon => String::from(";"),
             Token::Whitespace => String::new(),
         };
         write!(f, "{}", t)
@@ -73,7 +205,7 @@ pub struct Operator {
 }
 
 impl Operator {
-    /// Public function that returns whether this operator has a lower precedence than another
+    /// Whether this operator has a lower precedence than another
     pub fn has_lower_precedence_than(&self, other: &Operator) -> bool {
         self.precedence < other.precedence
     }
@@ -0,0 +1,289 @@
+use ethers_core::abi::Uint;
+
+use crate::utils::{
+    errors::ExecutionError,
+    evm::{Opcode, Outcome},
+};
+
+/// Hook into the execution of bytecode
+pub trait Tracer {
+    /// Called before each instruction with the stack from bottom to top, the memory size,
+    /// the gas left and the gas the instruction costs
+    fn on_step(
+        &mut self,
+        pc: usize,
+        opcode: Opcode,
+        stack: &[Uint],
+        memory_size: usize,
+        gas: u64,
+        gas_cost: u64,
+    );
+
+    /// Called once the execution stops, with how it ended or the error that stopped it
+    fn on_end(&mut self, _gas_used: u64, _result: Result<&Outcome, &ExecutionError>) {}
+}
+
+/// The tracer that doesn't trace
+impl Tracer for () {
+    fn on_step(
+        &mut self,
+        _pc: usize,
+        _opcode: Opcode,
+        _stack: &[Uint],
+        _memory_size: usize,
+        _gas: u64,
+        _gas_cost: u64,
+    ) {
+    }
+}
+
+/// The state of the EVM before an instruction
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Step {
+    pub pc: usize,
+    pub opcode: Opcode,
+    /// Gas left before the instruction
+    pub gas: u64,
+    pub gas_cost: u64,
+    /// Stack from bottom to top
+    pub stack: Vec<Uint>,
+    /// Memory size in bytes
+    pub memory_size: usize,
+    /// Why the execution stopped at this instruction, if it did
+    pub error: Option<String>,
+}
+
+impl Step {
+    /// The step as an EIP-3155 JSON object
+    pub fn to_json(&self) -> String {
+        let stack = self
+            .stack
+            .iter()
+            .map(|u| format!("\"{:#x}\"", u))
+            .collect::<Vec<_>>()
+            .join(",");
+        let error = self
+            .error
+            .as_ref()
+            .map_or(String::new(), |e| format!(",\"error\":{}", json_string(e)));
+        format!(
+            "{{\"pc\":{},\"op\":{},\"gas\":\"{:#x}\",\"gasCost\":\"{:#x}\",\"memSize\":{},\"stack\":[{}],\"depth\":1,\"returnData\":\"0x\",\"refund\":0,\"opName\":\"{:?}\"{}}}",
+            self.pc,
+            self.opcode as u8,
+            self.gas,
+            self.gas_cost,
+            self.memory_size,
+            stack,
+            self.opcode,
+            error
+        )
+    }
+}
+
+/// Tracer recording every step of an execution
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Trace {
+    pub steps: Vec<Step>,
+    pub gas_used: u64,
+    /// Data returned by RETURN or REVERT
+    pub output: Vec<u8>,
+    /// Why the execution failed, if it did
+    pub error: Option<String>,
+}
+
+impl Tracer for Trace {
+    fn on_step(
+        &mut self,
+        pc: usize,
+        opcode: Opcode,
+        stack: &[Uint],
+        memory_size: usize,
+        gas: u64,
+        gas_cost: u64,
+    ) {
+        self.steps.push(Step {
+            pc,
+            opcode,
+            gas,
+            gas_cost,
+            stack: stack.to_vec(),
+            memory_size,
+            error: None,
+        });
+    }
+
+    fn on_end(&mut self, gas_used: u64, result: Result<&Outcome, &ExecutionError>) {
+        self.gas_used = gas_used;
+        let error = result.err();
+        self.error = error.map(|e| e.to_string());
+        if let Ok(outcome) = result {
+            self.output = outcome.output().to_vec();
+            // A revert fails the execution without failing any step
+            if let Outcome::Revert(data) = outcome {
+                self.error = Some(ExecutionError::Reverted(data.clone()).to_string());
+            }
+        }
+        // An invalid opcode fails before its step is recorded, other errors fail the last step
+        if let (Some(step), Some(e)) = (self.steps.last_mut(), error) {
+            if !matches!(e, ExecutionError::InvalidOpcode { .. }) {
+                step.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+impl Trace {
+    /// Write the trace in the EIP-3155 JSON lines format:
+    /// one line per step, then a summary line
+    ///
+    /// There is no state, so the summary has no `stateRoot`.
+    pub fn to_eip3155(&self) -> String {
+        let error = self
+            .error
+            .as_ref()
+            .map_or(String::new(), |e| format!(",\"error\":{}", json_string(e)));
+        let summary = format!(
+            "{{\"output\":\"{}\",\"gasUsed\":\"{:#x}\",\"pass\":{}{}}}",
+            self.output
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>(),
+            self.gas_used,
+            self.error.is_none(),
+            error
+        );
+        self.steps
+            .iter()
+            .map(Step::to_json)
+            .chain(std::iter::once(summary))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Quote and escape a string for JSON
+fn json_string(s: &str) -> String {
+    let mut json = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::tests::bytecode,
+        utils::{evm::EVM, gas::Fork},
+    };
+
+    #[test]
+    fn test_trace() {
+        // PUSH1 2 PUSH1 3 ADD
+        let b = bytecode("6002600301".to_owned());
+        let mut trace = Trace::default();
+        let mut evm = EVM::new(Fork::Cancun, 100);
+        assert_eq!(
+            evm.execute_traced(&b, &mut trace),
+            Ok(Outcome::Stop(Some(Uint::from(5))))
+        );
+        assert_eq!(trace.gas_used, 9);
+        assert_eq!(trace.error, None);
+        assert_eq!(
+            trace.steps.iter().map(|s| s.pc).collect::<Vec<_>>(),
+            [0, 2, 4]
+        );
+        assert_eq!(
+            trace.steps[2],
+            Step {
+                pc: 4,
+                opcode: Opcode::ADD,
+                gas: 94,
+                gas_cost: 3,
+                stack: vec![Uint::from(2), Uint::from(3)],
+                memory_size: 0,
+                error: None,
+            }
+        );
+
+        let expected = [
+            r#"{"pc":0,"op":96,"gas":"0x64","gasCost":"0x3","memSize":0,"stack":[],"depth":1,"returnData":"0x","refund":0,"opName":"PUSH1"}"#,
+            r#"{"pc":2,"op":96,"gas":"0x61","gasCost":"0x3","memSize":0,"stack":["0x2"],"depth":1,"returnData":"0x","refund":0,"opName":"PUSH1"}"#,
+            r#"{"pc":4,"op":1,"gas":"0x5e","gasCost":"0x3","memSize":0,"stack":["0x2","0x3"],"depth":1,"returnData":"0x","refund":0,"opName":"ADD"}"#,
+            r#"{"output":"","gasUsed":"0x9","pass":true}"#,
+        ]
+        .join("\n");
+        assert_eq!(trace.to_eip3155(), expected);
+    }
+
+    #[test]
+    fn test_trace_memory() {
+        // PUSH1 42 PUSH0 MSTORE PUSH1 1 PUSH1 31 RETURN
+        let b = bytecode("602A5F526001601FF3".to_owned());
+        let mut trace = Trace::default();
+        assert!(EVM::default().execute_traced(&b, &mut trace).is_ok());
+        assert_eq!(
+            trace
+                .steps
+                .iter()
+                .map(|s| s.memory_size)
+                .collect::<Vec<_>>(),
+            [0, 0, 0, 32, 32, 32]
+        );
+        assert_eq!(trace.output, [42]);
+        assert!(trace
+            .to_eip3155()
+            .ends_with(r#"{"output":"2a","gasUsed":"0x11","pass":true}"#));
+
+        // PUSH0 PUSH0 REVERT
+        let b = bytecode("5F5FFD".to_owned());
+        let mut trace = Trace::default();
+        assert!(EVM::default().execute_traced(&b, &mut trace).is_ok());
+        assert_eq!(trace.steps[2].error, None);
+        assert!(trace.to_eip3155().ends_with(
+            r#"{"output":"","gasUsed":"0x4","pass":false,"error":"execution reverted with 0x"}"#
+        ));
+    }
+
+    #[test]
+    fn test_trace_errors() {
+        // PUSH2 0x0100 PUSH1 2 EXP, out of gas on the dynamic cost
+        let b = bytecode("61010060020A".to_owned());
+        let mut trace = Trace::default();
+        assert!(EVM::new(Fork::Cancun, 100)
+            .execute_traced(&b, &mut trace)
+            .is_err());
+        let last = trace.steps.last().unwrap();
+        assert_eq!(
+            (last.opcode, last.gas, last.gas_cost),
+            (Opcode::EXP, 94, 110)
+        );
+        assert_eq!(
+            last.error.as_deref(),
+            Some("out of gas at offset 5 (gas limit 100)")
+        );
+        assert!(trace
+            .to_eip3155()
+            .ends_with(r#"{"output":"","gasUsed":"0x64","pass":false,"error":"out of gas at offset 5 (gas limit 100)"}"#));
+
+        // Invalid opcodes have no step
+        let b = bytecode("5FEE".to_owned());
+        let mut trace = Trace::default();
+        assert!(EVM::default().execute_traced(&b, &mut trace).is_err());
+        assert_eq!(trace.steps.len(), 1);
+        assert_eq!(trace.steps[0].error, None);
+        assert_eq!(
+            trace.error.as_deref(),
+            Some("invalid opcode 0xee at offset 1")
+        );
+
+        assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\u000a""#);
+    }
+}
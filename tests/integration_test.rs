@@ -1,11 +1,843 @@
-use mini_evm_calculator::{calculate, utils::errors::LexicalError, Uint};
+use mini_evm_calculator::{
+    assembler::assemble,
+    calculate, calculate_signed, calculate_with,
+    codegen::{self, Bytecode},
+    compile_with,
+    lexer::{self, NumberError},
+    parser,
+    pipeline::Log,
+    trace_with,
+    utils::{
+        errors::{AssemblyError, CompileError, Error, ExecutionError, LexicalError},
+        evm::{disassemble, Instruction, Opcode, Outcome, EVM},
+        span::Span,
+        tracer::Trace,
+    },
+    Calculation, Contract, Fork, Mode, Options, Pipeline, ResultFormat, Session, Uint, I256,
+};
+
+/// The value of an unsigned calculation
+fn value(source: &str) -> Result<Uint, Error> {
+    calculate(source).map(|c| c.value)
+}
+
+/// The value of a signed calculation
+fn signed_value(source: &str) -> Result<I256, Error> {
+    calculate_signed(source).map(|c| c.value)
+}
 
 #[test]
 fn test_mini_evm_calculator() {
     let source = "156 + 4 * 3 / 2";
-    assert_eq!(calculate(source), Ok(Uint::from_dec_str("162").unwrap()));
+    assert_eq!(value(source), Ok(Uint::from_dec_str("162").unwrap()));
     let source = "(156 + 4) * 3 / 2";
-    assert_eq!(calculate(source), Ok(Uint::from_dec_str("240").unwrap()));
-    let source = "(156 + 4) * 3 ^ 2";
-    assert_eq!(calculate(source), Err(LexicalError::InvalidCharacter('^')));
+    assert_eq!(value(source), Ok(Uint::from_dec_str("240").unwrap()));
+    let source = "(156 + 4) * 3 @ 2";
+    assert_eq!(
+        value(source),
+        Err(Error::Lexical(LexicalError::InvalidCharacter(
+            '@',
+            Span::new(14, 15)
+        )))
+    );
+}
+
+#[test]
+fn test_evm_arithmetic_semantics() {
+    assert_eq!(value("0 - 1"), Ok(Uint::MAX));
+    assert_eq!(value("1 / 0"), Ok(Uint::zero()));
+    assert_eq!(value("7 / 2"), Ok(Uint::from(3)));
+    let max = Uint::MAX.to_string();
+    assert_eq!(value(&format!("{} + 1", max)), Ok(Uint::zero()));
+    assert_eq!(value(&format!("{} * {}", max, max)), Ok(Uint::one()));
+}
+
+#[test]
+fn test_unary_minus() {
+    assert_eq!(value("-3 + 5"), Ok(Uint::from(2)));
+    assert_eq!(value("4 * (-2)"), Ok(Uint::MAX - 7));
+    assert_eq!(value("--2"), Ok(Uint::from(2)));
+    assert_eq!(signed_value("4 * (-2)"), Ok(I256::from(-8)));
+    assert_eq!(signed_value("-7 / 2"), Ok(I256::from(-3)));
+    assert_eq!(signed_value("-7 / -2"), Ok(I256::from(3)));
+    assert_eq!(signed_value("1 - 2 * 3"), Ok(I256::from(-5)));
+    // Unsigned division of a negative number sees a huge uint256
+    assert_eq!(value("-7 / 2"), Ok((Uint::MAX - 6) / 2));
+}
+
+#[test]
+fn test_operators() {
+    assert_eq!(value("(156 + 4) * 3 ^ 2"), Ok(Uint::from(482)));
+    assert_eq!(value("17 % 5"), Ok(Uint::from(2)));
+    assert_eq!(value("2 ** 3 ** 2"), Ok(Uint::from(512)));
+    assert_eq!(value("2 ** 255 * 2"), Ok(Uint::zero()));
+    assert_eq!(value("6 & 3 | 8"), Ok(Uint::from(10)));
+    assert_eq!(value("~0"), Ok(Uint::MAX));
+    assert_eq!(value("1 << 4 + 1"), Ok(Uint::from(32)));
+    assert_eq!(value("256 >> 4"), Ok(Uint::from(16)));
+    assert_eq!(value("1 << 256"), Ok(Uint::zero()));
+    assert_eq!(signed_value("-2 ** 2"), Ok(I256::from(-4)));
+    assert_eq!(signed_value("-7 % 3"), Ok(I256::from(-1)));
+    assert_eq!(signed_value("-16 >> 2"), Ok(I256::from(-4)));
+    assert_eq!(signed_value("~0"), Ok(I256::from(-1)));
+}
+
+#[test]
+fn test_calls() {
+    assert_eq!(value("addmod(2 ** 255, 2 ** 255, 7)"), Ok(Uint::from(2)));
+    assert_eq!(
+        value("mulmod(2 ** 200, 2 ** 100, 2 ** 255) + 1"),
+        Ok(Uint::from(1))
+    );
+    assert_eq!(
+        value("sqrt(4)"),
+        Err(Error::Compile(CompileError::UnknownFunction(
+            "sqrt".to_owned(),
+            Span::new(0, 7)
+        )))
+    );
+    assert_eq!(
+        value("x + 1"),
+        Err(Error::Compile(CompileError::UndefinedVariable(
+            "x".to_owned(),
+            Span::new(0, 1)
+        )))
+    );
+}
+
+#[test]
+fn test_single_result_on_stack() {
+    let sources = [
+        "1",
+        "-1",
+        "~-(1)",
+        "156 + 4 * 3 / 2",
+        "(156 + 4) * 3 / 2",
+        "2 ** 3 ** 2 % 5 - 1",
+        "1 << 2 >> 1 | 4 & 5 ^ 6",
+        "addmod(1, mulmod(2, 3, 4), -5)",
+        "((((1))))",
+    ];
+    for source in sources {
+        let ast = parser::parse(lexer::lexer(source).unwrap()).unwrap();
+        let bytecode = codegen::compile(&ast, &Options::default()).unwrap();
+        let mut evm = EVM::default();
+        evm.execute(&bytecode).unwrap();
+        assert_eq!(evm.stack.len(), 1, "{}", source);
+    }
+}
+
+#[test]
+fn test_malformed_expressions() {
+    for source in ["1 2", "+", "3 +", "()", "1 + (2 3)", "* 2", "2 -", "f(1,)"] {
+        assert!(
+            matches!(value(source), Err(Error::Lexical(_))),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_minimal_push_bytecode() {
+    let compile = |s: &str| {
+        let ast = parser::parse(lexer::lexer(s).unwrap()).unwrap();
+        codegen::compile(&ast, &Options::default())
+            .unwrap()
+            .to_string()
+    };
+    // PUSH1 2 PUSH0 ADD
+    assert_eq!(compile("0 + 2"), "60025F01");
+    assert_eq!(value("0 + 2"), Ok(Uint::from(2)));
+    assert_eq!(value("65535 + 65536"), Ok(Uint::from(131071)));
+}
+
+#[test]
+fn test_bytecode_hex_round_trip() {
+    let ast = parser::parse(lexer::lexer("(156 + 4) * 3 / 2").unwrap()).unwrap();
+    let bytecode = codegen::compile(&ast, &Options::default()).unwrap();
+    let hex = format!("{:#x}", bytecode);
+    assert_eq!(hex, "0x6004609c0160030260029004");
+
+    let decoded: Bytecode = hex.parse().unwrap();
+    assert_eq!(decoded, bytecode);
+    assert_eq!(decoded.as_ref()[..2], [0x60, 0x04]);
+    assert_eq!(
+        EVM::default().execute(&decoded),
+        Ok(Outcome::Stop(Some(Uint::from(240))))
+    );
+    assert_eq!(
+        EVM::default().execute(&"0X600260036004609C010204".parse().unwrap()),
+        Ok(Outcome::Stop(Some(Uint::from(240))))
+    );
+}
+
+#[test]
+fn test_gas_metering() {
+    // PUSH1 2 PUSH1 1 ADD
+    assert_eq!(
+        calculate("1 + 2"),
+        Ok(Calculation {
+            value: Uint::from(3),
+            gas_used: 9
+        })
+    );
+    // PUSH1 2 PUSH1 3 PUSH1 2 EXP MUL
+    assert_eq!(
+        calculate("2 ** 3 * 2").map(|c| c.gas_used),
+        Ok(3 * 3 + 10 + 50 + 5)
+    );
+    assert_eq!(
+        calculate_signed("-1").map(|c| (c.value, c.gas_used)),
+        Ok((I256::from(-1), 3 + 2 + 3))
+    );
+
+    // PUSH0 is replaced by PUSH1 0 before Shanghai
+    let mut options = Options {
+        fork: Fork::Berlin,
+        ..Options::default()
+    };
+    assert_eq!(calculate_with("0 + 1", &options).map(|c| c.gas_used), Ok(9));
+    options.fork = Fork::Shanghai;
+    assert_eq!(calculate_with("0 + 1", &options).map(|c| c.gas_used), Ok(8));
+
+    options.gas_limit = 7;
+    assert_eq!(
+        calculate_with("0 + 1", &options),
+        Err(Error::Execution(ExecutionError::OutOfGas {
+            offset: 3,
+            gas_limit: 7
+        }))
+    );
+}
+
+#[test]
+fn test_execution_trace() {
+    let mut trace = Trace::default();
+    let calculation = trace_with("(1 + 2) * 3", &Options::default(), &mut trace).unwrap();
+    assert_eq!(calculation.value, Uint::from(9));
+    assert_eq!(trace.gas_used, calculation.gas_used);
+    let names: Vec<_> = trace
+        .steps
+        .iter()
+        .map(|s| format!("{:?}", s.opcode))
+        .collect();
+    assert_eq!(names, ["PUSH1", "PUSH1", "ADD", "PUSH1", "MUL"]);
+    // The stack is recorded before each step, from bottom to top
+    assert_eq!(trace.steps[4].stack, [Uint::from(3), Uint::from(3)]);
+    let lines: Vec<_> = trace.to_eip3155().lines().map(str::to_owned).collect();
+    assert_eq!(lines.len(), 6);
+    assert!(lines[2].starts_with(r#"{"pc":4,"op":1,"#));
+    assert_eq!(lines[5], r#"{"output":"","gasUsed":"0x11","pass":true}"#);
+}
+
+#[test]
+fn test_disassembly() {
+    let bytecode = compile_with("-(1 + 256)", &Options::default()).unwrap();
+    let listing: Vec<_> = disassemble(&bytecode)
+        .iter()
+        .map(|i| i.to_string())
+        .collect();
+    assert_eq!(
+        listing,
+        [
+            "0x0000: PUSH2 0x0100",
+            "0x0003: PUSH1 0x01",
+            "0x0005: ADD",
+            "0x0006: PUSH0",
+            "0x0007: SUB",
+        ]
+    );
+    let bytecode: Bytecode = "0x60".parse().unwrap();
+    assert_eq!(
+        disassemble(&bytecode),
+        [Instruction::Truncated {
+            offset: 0,
+            opcode: Opcode::PUSH1,
+            immediate: vec![]
+        }]
+    );
+}
+
+#[test]
+fn test_assembler() {
+    // The snippet of the codegen comments
+    let bytecode = assemble("PUSH 3 PUSH 4 MUL PUSH 2 SWAP1 DIV PUSH 156 ADD").unwrap();
+    assert_eq!(
+        EVM::default().execute(&bytecode),
+        Ok(Outcome::Stop(Some(Uint::from(162))))
+    );
+    assert_eq!(
+        Some(bytecode),
+        compile_with("156 + 4 * 3 / 2", &Options::default()).ok()
+    );
+
+    // Compiled code round trips through the disassembler
+    let bytecode = compile_with("addmod(2 ** 255, -1, 7) << 3", &Options::default()).unwrap();
+    let listing: Vec<_> = disassemble(&bytecode)
+        .iter()
+        .map(|i| i.to_string())
+        .collect();
+    assert_eq!(assemble(&listing.join("\n")), Ok(bytecode));
+
+    // Labels are jump destinations: max(7, 9) with a conditional jump
+    let source = "
+        PUSH 9 PUSH 7                ; b a
+        DUP2 DUP2 SLT                ; a < b
+        PUSH a_is_less JUMPI
+        SWAP1 POP PUSH end JUMP      ; a
+        a_is_less: JUMPDEST POP      ; b
+        end: JUMPDEST
+    ";
+    let bytecode = assemble(source).unwrap();
+    let mut evm = EVM::default();
+    assert_eq!(
+        evm.execute(&bytecode),
+        Ok(Outcome::Stop(Some(Uint::from(9))))
+    );
+    assert_eq!(evm.stack.len(), 1);
+    // Jumping into the middle of an instruction fails
+    let bytecode = assemble("PUSH2 0x5b5b PUSH 2 JUMP").unwrap();
+    assert!(matches!(
+        EVM::default().execute(&bytecode),
+        Err(ExecutionError::InvalidJump { offset: 5, .. })
+    ));
+
+    assert_eq!(
+        assemble("PUSH1 2 PUSH1 x"),
+        Err(AssemblyError::UndefinedLabel(
+            "x".to_owned(),
+            Span::new(14, 15)
+        ))
+    );
+}
+
+#[test]
+fn test_let_bindings() {
+    assert_eq!(value("let x = 3 * 4; x + 1"), Ok(Uint::from(13)));
+    assert_eq!(
+        value("let gas = 21000; let price = 30; let fee = gas * price; fee + fee / 10"),
+        Ok(Uint::from(693000))
+    );
+    assert_eq!(
+        value("let x = 2; (let x = x ** 3; x) - x"),
+        Ok(Uint::from(6))
+    );
+    assert_eq!(signed_value("let x = -7; x / 2"), Ok(I256::from(-3)));
+    // Bindings don't leak onto the stack
+    let mut evm = EVM::default();
+    let bytecode = compile_with("let a = 1; let b = 2; a + b", &Options::default()).unwrap();
+    assert_eq!(
+        evm.execute(&bytecode),
+        Ok(Outcome::Stop(Some(Uint::from(3))))
+    );
+    assert_eq!(evm.stack.len(), 1);
+
+    assert_eq!(
+        value("let x = 1; y"),
+        Err(Error::Compile(CompileError::UndefinedVariable(
+            "y".to_owned(),
+            Span::new(11, 12)
+        )))
+    );
+    assert_eq!(
+        value("let = 1; 2"),
+        Err(Error::Lexical(LexicalError::Expected(
+            "a name after 'let'".to_owned(),
+            Span::new(4, 5)
+        )))
+    );
+}
+
+#[test]
+fn test_conditionals() {
+    assert_eq!(value("1 < 2"), Ok(Uint::one()));
+    assert_eq!(value("2 <= 1"), Ok(Uint::zero()));
+    assert_eq!(value("3 >= 3 && 4 != 5"), Ok(Uint::one()));
+    assert_eq!(value("0 || 7 == 7"), Ok(Uint::one()));
+    assert_eq!(value("!0 + !5"), Ok(Uint::one()));
+    assert_eq!(value("6 & 3 == 2"), Ok(Uint::one()));
+    // -1 is the largest uint256 but the smallest int256
+    assert_eq!(value("-1 > 1"), Ok(Uint::one()));
+    assert_eq!(signed_value("-1 > 1"), Ok(I256::zero()));
+    assert_eq!(signed_value("-1 <= -1"), Ok(I256::one()));
+
+    // Fee tiers
+    let fee = |gas: u64| {
+        value(&format!(
+            "let gas = {}; gas <= 21000 ? 1 gwei : gas <= 100000 ? 2 gwei : 3 gwei",
+            gas
+        ))
+    };
+    let gwei = Uint::exp10(9);
+    assert_eq!(fee(21000), Ok(gwei));
+    assert_eq!(fee(50000), Ok(gwei * 2));
+    assert_eq!(fee(100001), Ok(gwei * 3));
+    assert_eq!(
+        value("let gas = 50000; if gas > 21000 then gas * 2 else gas"),
+        Ok(Uint::from(100000))
+    );
+    assert_eq!(
+        value("let gas = 20000; 1 + if gas > 21000 then gas * 2 else gas"),
+        Ok(Uint::from(20001))
+    );
+    assert_eq!(
+        signed_value("let x = -5; x < 0 ? -x : x"),
+        Ok(I256::from(5))
+    );
+
+    // Only the branch taken is executed, and it leaves a single value on the stack
+    let bytecode = compile_with("let x = 3; x > 2 ? x * x : x + 1", &Options::default()).unwrap();
+    let mut evm = EVM::default();
+    assert_eq!(
+        evm.execute(&bytecode),
+        Ok(Outcome::Stop(Some(Uint::from(9))))
+    );
+    assert_eq!(evm.stack.len(), 1);
+    // A constant condition keeps a single branch
+    let optimized = Options {
+        optimize: true,
+        ..Options::default()
+    };
+    let bytecode = compile_with("if 2 > 1 then 3 else 4", &optimized).unwrap();
+    assert_eq!(bytecode.to_string(), "6003");
+
+    assert_eq!(
+        value("1 ? 2"),
+        Err(Error::Lexical(LexicalError::Expected(
+            "':' after the first branch of '?'".to_owned(),
+            Span::new(5, 5)
+        )))
+    );
+    assert_eq!(
+        value("if 1 then 2"),
+        Err(Error::Lexical(LexicalError::Expected(
+            "'else' after the 'then' branch".to_owned(),
+            Span::new(11, 11)
+        )))
+    );
+}
+
+#[test]
+fn test_nesting_limit() {
+    // Too deep for the stack of the parser, the optimizer and the codegen
+    let source = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+    assert_eq!(
+        value(&source),
+        Err(Error::Lexical(LexicalError::TooDeep(Span::new(128, 129))))
+    );
+    assert_eq!(
+        value(&format!("{}1", "-".repeat(100_000)))
+            .unwrap_err()
+            .to_string(),
+        "Expression nested more than 128 levels deep"
+    );
+    let source = format!("{}1{}", "(".repeat(127), ")".repeat(127));
+    assert_eq!(value(&source), Ok(Uint::one()));
+    let source = format!("{}1", "-".repeat(127));
+    assert_eq!(value(&source), Ok(Uint::MAX));
+
+    // Flat chains don't nest, so they can be much longer
+    let sum = vec!["1"; 4097].join("+");
+    assert_eq!(value(&sum), Ok(Uint::from(4097)));
+    let options = Options {
+        optimize: false,
+        ..Options::default()
+    };
+    let source = format!("let x = 1; {}", vec!["x"; 4097].join(" - "));
+    let expected = Uint::MAX - Uint::from(4094);
+    assert_eq!(
+        calculate_with(&source, &options).map(|c| c.value),
+        Ok(expected)
+    );
+    assert_eq!(value(&source), Ok(expected));
+    let ast = parser::parse(lexer::lexer(&sum).unwrap()).unwrap();
+    assert_eq!(ast.to_string(), vec!["1"; 4097].join(" + "));
+    assert_eq!(
+        ast.to_sexp(),
+        format!("{}1{}", "(add ".repeat(4096), " 1)".repeat(4096))
+    );
+    // Down to the first 1, chained right after the parentheses
+    let source = format!("{}{}{}", "(".repeat(120), sum, ")".repeat(120));
+    assert_eq!(value(&source), Ok(Uint::from(4097)));
+    assert_eq!(
+        value(&vec!["1"; 10_000].join("+")).unwrap_err().to_string(),
+        "Expression chaining more than 4096 operations"
+    );
+}
+
+#[test]
+fn test_session() {
+    let mut session = Session::new(Options {
+        fork: Fork::Berlin,
+        ..Options::default()
+    });
+    assert_eq!(
+        session.eval("gas = 21000"),
+        Ok(Calculation {
+            value: Uint::from(21000),
+            gas_used: 3
+        })
+    );
+    assert_eq!(
+        session.eval("price = 30").map(|c| c.value),
+        Ok(Uint::from(30))
+    );
+    assert_eq!(
+        session.eval("gas * price").map(|c| c.value),
+        Ok(Uint::from(630000))
+    );
+    assert_eq!(
+        session.eval("ans + $1 / 10").map(|c| c.value),
+        Ok(Uint::from(632100))
+    );
+    // Session values are pushed like literals
+    assert_eq!(
+        session.compile("_ - price"),
+        Ok("601E6209A52403".parse().unwrap())
+    );
+    assert_eq!(
+        session.eval("$1 = 2"),
+        Err(Error::Compile(CompileError::ReservedName(
+            "$1".to_owned(),
+            Span::new(0, 2)
+        )))
+    );
+    assert_eq!(session.history.len(), 4);
+}
+
+#[test]
+fn test_command_line() {
+    use std::{io::Write, process::Command, process::Stdio};
+
+    let cli = |args: &[&str], stdin: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_mini_evm_calculator"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        (
+            output.status.code(),
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    assert_eq!(
+        cli(&["-e", "1 + 2", "-e", "ans * 2"], ""),
+        (Some(0), "3\n6\n".to_owned(), String::new())
+    );
+    // Piped stdin has no banner nor prompt
+    let (code, stdout, stderr) = cli(&[], "x = 3\n\nx ** 2\n1 @ 2\n");
+    assert_eq!((code, stdout.as_str()), (Some(1), "3\n9\n"));
+    let expected = [
+        "error: Invalid character: @",
+        " --> stdin:4:3",
+        "  |",
+        "4 | 1 @ 2",
+        "  |   ^",
+        "",
+    ];
+    assert_eq!(stderr, expected.join("\n"));
+    // Columns count the indentation of the line
+    let expected = [
+        "error: Missing operand after '*'",
+        " --> -e:1:5",
+        "  |",
+        "1 |   2 *",
+        "  |     ^",
+        "",
+    ];
+    assert_eq!(cli(&["-e", "  2 *"], "").2, expected.join("\n"));
+
+    assert_eq!(
+        cli(&["--emit", "pn", "-e", "1 + 2 * 3"], "").1,
+        "(add 1 (mul 2 3))\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "ast", "-e", "let x = 2; (x * 3) + (1)"], "").1,
+        "let x = 2; x * 3 + 1\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "tokens", "-e", "2*(3)"], "").1,
+        "2 * ( 3 )\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "tokens", "-e", "let x = 1;  x  +  2"], "").1,
+        "let x = 1 ; x + 2\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "bytecode", "--fork", "berlin", "-"], "0 - 1").1,
+        "0x6001600003\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "asm", "-e", "0 - 1"], "").1,
+        "0x0000: PUSH1 0x01\n0x0002: PUSH0\n0x0003: SUB\n"
+    );
+    assert_eq!(cli(&["--mode", "signed", "-e", "-8 / 2"], "").1, "-4\n");
+    assert_eq!(cli(&["--gas", "2", "-e", "1 + 2"], "").0, Some(1));
+    // Optimized unless asked otherwise
+    assert_eq!(
+        cli(&["--emit", "bytecode", "-e", "2 * 3 + 1"], "").1,
+        "0x6007\n"
+    );
+    assert_eq!(
+        cli(
+            &["--emit", "bytecode", "--no-optimize", "-e", "2 * 3 + 1"],
+            ""
+        )
+        .1,
+        "0x6003600202600101\n"
+    );
+    assert_eq!(
+        cli(&["--format", "gwei", "-e", "20 gwei * 3"], "").1,
+        "60 gwei\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "runtime", "-e", "4 * 5"], "").1,
+        "0x60145f5260205ff3\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "init", "-e", "4 * 5"], "").1,
+        "0x6008600a5f3960085ff360145f5260205ff3\n"
+    );
+    // Other stages don't run the calculation, unless it is assigned
+    assert_eq!(
+        cli(&["--gas", "1", "--emit", "bytecode", "-e", "1 + 2"], ""),
+        (Some(0), "0x6003\n".to_owned(), String::new())
+    );
+    let (code, stdout, stderr) = cli(&["--gas", "1", "--emit", "pn", "-"], "x = 1 + 2\nx\n");
+    assert_eq!((code, stdout.as_str()), (Some(1), "(add 1 2)\nx\n"));
+    assert_eq!(
+        stderr,
+        "error: Execution error: out of gas at offset 0 (gas limit 1)\n --> stdin:1\n"
+    );
+    assert_eq!(
+        cli(&["--emit", "pn", "-"], "x = 1 + 2\nx * 2\n$1"),
+        (
+            Some(0),
+            "(add 1 2)\n(mul x 2)\n$1\n".to_owned(),
+            String::new()
+        )
+    );
+    assert_eq!(cli(&["--emit", "nothing"], "").0, Some(2));
+    assert_eq!(cli(&["-h"], "").0, Some(0));
+}
+
+#[test]
+fn test_pipeline() {
+    let options = Options {
+        mode: Mode::Signed,
+        ..Options::default()
+    };
+    let mut pipeline = Pipeline::new(options).with_observer(Log(Vec::new()));
+    pipeline.trace = true;
+    let compilation = pipeline.run("-7 / 2").unwrap();
+    assert_eq!(compilation.pn(), "(div (neg 7) 2)");
+    assert_eq!(
+        compilation.bytecode,
+        compile_with("-7 / 2", &options).unwrap()
+    );
+    assert_eq!(
+        compilation.trace.steps.last().map(|s| s.opcode),
+        Some(Opcode::SDIV)
+    );
+    assert_eq!(
+        compilation.result.map(|c| I256::from_raw(c.value)),
+        Some(I256::from(-3))
+    );
+    let log = String::from_utf8(pipeline.into_observer().0).unwrap();
+    assert_eq!(log.lines().count(), 4);
+}
+
+#[test]
+fn test_number_literals() {
+    assert_eq!(value("0xff + 0b1 * 0o10"), Ok(Uint::from(263)));
+    assert_eq!(value("1_000_000 / 1_000"), Ok(Uint::from(1000)));
+    assert_eq!(
+        value(&format!("0x{} + 1", "F".repeat(64))),
+        Ok(Uint::zero())
+    );
+    let too_large = format!("0x1{}", "0".repeat(64));
+    let source = format!("1 + {}", too_large);
+    let err = value(&source).unwrap_err();
+    assert_eq!(
+        err,
+        Error::Lexical(LexicalError::InvalidNumber(
+            too_large.clone(),
+            NumberError::Overflow,
+            Span::new(4, 71)
+        ))
+    );
+    assert_eq!(
+        err.to_string(),
+        format!("Invalid number: {} is larger than 2^256 - 1", too_large)
+    );
+    assert_eq!(
+        value("0b12"),
+        Err(Error::Lexical(LexicalError::InvalidNumber(
+            "0b12".to_owned(),
+            NumberError::Malformed,
+            Span::new(0, 4)
+        )))
+    );
+    assert_eq!(
+        value("\u{663}"),
+        Err(Error::Lexical(LexicalError::InvalidCharacter(
+            '\u{663}',
+            Span::new(0, 2)
+        )))
+    );
+}
+
+#[test]
+fn test_units() {
+    assert_eq!(value("1.5 ether / 3"), Ok(Uint::exp10(17) * 5));
+    assert_eq!(
+        value("20 gwei * 21000"),
+        Ok(Uint::from(420_000_000_000_000u64))
+    );
+    assert_eq!(value("1e18 - 1 ether"), Ok(Uint::zero()));
+    assert_eq!(
+        value("2 * 1.5 wei").map_err(|e| e.to_string()),
+        Err("Invalid number: 1.5 wei is not a whole number of wei".to_owned())
+    );
+    assert_eq!(
+        value("0x10 ether").map_err(|e| e.to_string()),
+        Err("Invalid number: 0x10 ether has a unit but isn't decimal".to_owned())
+    );
+}
+
+#[test]
+fn test_result_formats() {
+    let res = value("1.5 ether").unwrap();
+    assert_eq!(ResultFormat::Ether.format(res), "1.5 ether");
+    assert_eq!(ResultFormat::Gwei.format(res), "1500000000 gwei");
+    assert_eq!(ResultFormat::Hex.format(res), "0x14d1120d7b160000");
+    let res = value("0 - 2").unwrap();
+    assert_eq!(ResultFormat::Signed.format(res), "-2");
+    assert_eq!(
+        ResultFormat::Word.format(res),
+        format!("0x{}e", "f".repeat(63))
+    );
+    assert_eq!(ResultFormat::Binary.format(value("5").unwrap()), "0b101");
+
+    let mut session = Session {
+        format: "ether".parse().unwrap(),
+        ..Session::default()
+    };
+    let res = session.eval("1 ether / 4").unwrap();
+    assert_eq!(session.format(res.value), "0.25 ether");
+}
+
+#[test]
+fn test_optimizer() {
+    let options = Options {
+        optimize: true,
+        ..Options::default()
+    };
+    let unoptimized = Options::default();
+    for source in [
+        "156 + 4 * 3 / 2",
+        "let x = 5; x * 8 + 0",
+        "let x = 5; x * 1 - 0 + (x ** 1) / 1",
+        "let a = 7; let b = a * 16; b | 0",
+        "addmod(2 ** 255, -1, 7) << 3",
+        "let x = 3; 4 * x",
+    ] {
+        let optimized = calculate_with(source, &options).unwrap();
+        let plain = calculate_with(source, &unoptimized).unwrap();
+        assert_eq!(optimized.value, plain.value, "{}", source);
+        assert!(optimized.gas_used <= plain.gas_used, "{}", source);
+    }
+    let signed = Options {
+        mode: Mode::Signed,
+        ..options
+    };
+    assert_eq!(
+        calculate_with("let x = -3; x * 4", &signed).map(|c| I256::from_raw(c.value)),
+        Ok(I256::from(-12))
+    );
+
+    let bytecode = compile_with("(1 + 2) * 3", &options).unwrap();
+    assert_eq!(bytecode, "6009".parse().unwrap());
+    let compilation = Pipeline::new(options).run("let x = 1; x").unwrap();
+    assert_eq!(compilation.bytecode, "6001".parse().unwrap());
+    assert_eq!(
+        compilation.optimization.map(|r| r.to_string()),
+        Some("size: 5 -> 2 bytes, gas: 11 -> 3".to_owned())
+    );
+}
+
+#[test]
+fn test_contract() {
+    let bytecode = compile_with("4 * 5", &Options::default()).unwrap();
+    let contract = Contract::new(&bytecode, Fork::Cancun);
+    assert!(contract.runtime.as_ref().starts_with(bytecode.as_ref()));
+    assert!(contract.init.as_ref().ends_with(contract.runtime.as_ref()));
+
+    let opcodes = |bytecode: &Bytecode| {
+        disassemble(bytecode)
+            .into_iter()
+            .filter_map(|i| match i {
+                Instruction::Op { opcode, .. } => Some(opcode),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let runtime = opcodes(&contract.runtime);
+    assert_eq!(
+        runtime[runtime.len() - 5..],
+        [
+            Opcode::PUSH0,
+            Opcode::MSTORE,
+            Opcode::PUSH1,
+            Opcode::PUSH0,
+            Opcode::RETURN
+        ]
+    );
+    assert!(opcodes(&contract.init).contains(&Opcode::CODECOPY));
+
+    // Deploying returns the runtime code, which returns the result
+    let deployed = EVM::default().execute(&contract.init).unwrap();
+    assert!(matches!(deployed, Outcome::Return(_)));
+    assert_eq!(deployed.output(), contract.runtime.as_ref());
+    let mut evm = EVM::default();
+    let outcome = evm.execute(&contract.runtime).unwrap();
+    assert_eq!(outcome.value(), Ok(Uint::from(20)));
+    assert!(evm.stack.is_empty());
+    for fork in [Fork::Berlin, Fork::Shanghai] {
+        let contract = Contract::new(
+            &compile_with(
+                "4 * 5",
+                &Options {
+                    fork,
+                    ..Options::default()
+                },
+            )
+            .unwrap(),
+            fork,
+        );
+        let runtime = EVM::new(fork, 100).execute(&contract.init).unwrap();
+        let outcome = EVM::new(fork, 100).execute(&runtime.output().to_vec().into());
+        assert_eq!(outcome.and_then(|o| o.value()), Ok(Uint::from(20)));
+    }
+
+    // The session compiles with its values
+    let mut session = Session::default();
+    session.eval("x = 4").unwrap();
+    assert_eq!(session.contract("x * 5"), Ok(contract));
+    assert!(matches!(
+        session.contract("y * 5"),
+        Err(Error::Compile(CompileError::UndefinedVariable(..)))
+    ));
 }